@@ -4,4 +4,17 @@ fn main() {
     let version = version.trim_start_matches('v');
     println!("cargo:rustc-env=TRUSTTUNNEL_VERSION={}", version);
     println!("cargo:rerun-if-env-changed=TRUSTTUNNEL_VERSION");
+
+    // A system clock behind the build time has not been set yet.
+    let build_time = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    println!("cargo:rustc-env=TRUSTTUNNEL_BUILD_TIME={}", build_time);
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
 }
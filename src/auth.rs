@@ -2,12 +2,19 @@ use md5::{Digest as Md5Digest, Md5};
 #[cfg(unix)]
 use nix::ifaddrs::getifaddrs;
 use sha2::Sha256;
-#[cfg(unix)]
-use std::net::Ipv4Addr;
 
 const BRIDGE_INTERFACES: &[&str] = &["br0", "br-lan"];
 const FALLBACK_HOST: &str = "192.168.1.1";
 
+/// Configured NDM host, or the auto-detected LAN IP when empty.
+pub fn resolve_ndm_host(configured: &str) -> String {
+    if configured.is_empty() {
+        detect_ndm_host()
+    } else {
+        configured.to_string()
+    }
+}
+
 /// Auto-detect the router's LAN IP from the bridge interface.
 pub fn detect_ndm_host() -> String {
     #[cfg(unix)]
@@ -19,7 +26,7 @@ pub fn detect_ndm_host() -> String {
                 }
                 if let Some(addr) = ifaddr.address {
                     if let Some(sin) = addr.as_sockaddr_in() {
-                        let ip = Ipv4Addr::from(sin.ip());
+                        let ip = sin.ip();
                         if !ip.is_loopback() && !ip.is_unspecified() {
                             log::info!(
                                 "NDM auth: detected LAN IP {} on {}",
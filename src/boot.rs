@@ -0,0 +1,125 @@
+use crate::logs;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Build time of this binary, Unix seconds. Routers without a battery-backed
+/// clock boot at 1970 until NTP catches up, and TLS fails until then.
+fn clock_floor() -> u64 {
+    env!("TRUSTTUNNEL_BUILD_TIME").parse().unwrap_or(0)
+}
+
+/// Readiness of what the first connect after boot depends on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Gates {
+    /// Interface of the default WAN route.
+    wan: Option<String>,
+    /// Last NDM error; `None` once NDM answers.
+    ndm_error: Option<String>,
+    /// System time, Unix seconds.
+    clock: u64,
+}
+
+impl Gates {
+    fn check() -> Self {
+        Self {
+            wan: crate::routing::current_wan_interface(),
+            ndm_error: crate::ndm::ready().err().map(|e| e.to_string()),
+            clock: crate::util::unix_now(),
+        }
+    }
+
+    fn clock_ok(&self) -> bool {
+        self.clock >= clock_floor()
+    }
+
+    fn ready(&self) -> bool {
+        self.wan.is_some() && self.ndm_error.is_none() && self.clock_ok()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "wan={} ndm={} clock={}",
+            self.wan.as_deref().unwrap_or("waiting"),
+            if self.ndm_error.is_none() {
+                "ok"
+            } else {
+                "waiting"
+            },
+            if self.clock_ok() {
+                "ok"
+            } else {
+                "behind build time"
+            }
+        )
+    }
+}
+
+/// Wait until a WAN default route exists, NDM answers and the clock is
+/// past the build time, or until `timeout` passes. Each change of a gate is
+/// logged. Returns whether every gate opened.
+pub fn wait(timeout: Duration) -> bool {
+    let started = Instant::now();
+    let mut last: Option<String> = None;
+    loop {
+        let gates = Gates::check();
+        if gates.ready() {
+            if last.is_some() {
+                report(format!(
+                    "[boot] ready after {}s: {}",
+                    started.elapsed().as_secs(),
+                    gates.describe()
+                ));
+            }
+            return true;
+        }
+        let status = gates.describe();
+        if last.as_deref() != Some(status.as_str()) {
+            report(format!("[boot] waiting before connecting: {}", status));
+            if let Some(e) = &gates.ndm_error {
+                log::debug!("[boot] ndm: {}", e);
+            }
+            last = Some(status);
+        }
+        if started.elapsed() >= timeout {
+            let msg = format!(
+                "[boot] gave up waiting after {}s, connecting anyway: {}",
+                timeout.as_secs(),
+                gates.describe()
+            );
+            log::warn!("{}", msg);
+            logs::global_buffer().push(msg);
+            return false;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn report(msg: String) {
+    log::info!("{}", msg);
+    logs::global_buffer().push(msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gates_ready_and_describe() {
+        let mut gates = Gates {
+            wan: None,
+            ndm_error: Some("rci transport: connection refused".into()),
+            clock: 0,
+        };
+        assert!(!gates.ready());
+        assert_eq!(
+            gates.describe(),
+            "wan=waiting ndm=waiting clock=behind build time"
+        );
+        gates.wan = Some("eth3".into());
+        gates.ndm_error = None;
+        gates.clock = clock_floor();
+        assert!(gates.ready());
+        assert_eq!(gates.describe(), "wan=eth3 ndm=ok clock=ok");
+    }
+}
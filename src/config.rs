@@ -14,6 +14,21 @@ pub struct WrapperConfig {
     pub logging: LogSettings,
     #[serde(default)]
     pub routing: RoutingSettings,
+    #[serde(default)]
+    pub route_lists: RouteListSettings,
+    #[serde(default)]
+    pub instance: InstanceSettings,
+    /// Additional tunnels run next to the `tunnel` block (which is `main`).
+    #[serde(default)]
+    pub tunnels: Vec<ExtraTunnel>,
+    #[serde(default)]
+    pub killswitch: KillSwitchSettings,
+    #[serde(default)]
+    pub dns: DnsSettings,
+    #[serde(default)]
+    pub schedule: ScheduleSettings,
+    #[serde(default)]
+    pub startup: StartupSettings,
 }
 
 /// Settings that map to TrustTunnelClient's TOML config.
@@ -97,7 +112,7 @@ pub struct LogSettings {
     pub file_path: String,
     #[serde(
         default = "default_rotate_size_bytes",
-        deserialize_with = "deserialize_rotate_size"
+        deserialize_with = "deserialize_size"
     )]
     pub rotate_size: u64,
     #[serde(default = "default_rotate_keep")]
@@ -118,6 +133,44 @@ pub struct RoutingSettings {
     pub watchdog_check_url: String,
     #[serde(default = "default_watchdog_check_timeout")]
     pub watchdog_check_timeout: u64,
+    /// Watchdog probes; empty means one HTTP probe of `watchdog_check_url`.
+    #[serde(default)]
+    pub watchdog_probes: Vec<ProbeSettings>,
+    /// Probes that must pass for the tunnel to count as healthy; 0 means all.
+    #[serde(default = "default_watchdog_quorum")]
+    pub watchdog_quorum: u32,
+    /// On a failed check, repeat the probes via WAN and hold restarts while
+    /// WAN is down too.
+    #[serde(default = "default_true")]
+    pub watchdog_wan_check: bool,
+    /// Recovery steps taken on failed checks, cheapest first. Empty means
+    /// `restart_client` after `watchdog_failures` failures.
+    #[serde(default)]
+    pub watchdog_ladder: Vec<LadderRung>,
+    #[serde(default)]
+    pub quality: QualitySettings,
+    #[serde(default)]
+    pub sessions: SessionSettings,
+    #[serde(default)]
+    pub quota: QuotaSettings,
+    #[serde(default = "default_journal_path")]
+    pub journal_path: String,
+    #[serde(default = "default_resolve_interval")]
+    pub resolve_interval: u64,
+    /// DNS servers (`IP:port`) queried out of the WAN interface when
+    /// endpoint hostnames are re-resolved.
+    #[serde(default = "default_resolve_servers")]
+    pub resolve_servers: Vec<String>,
+    /// `auto` (RCI with ndmc fallback), `rci` or `ndmc`.
+    #[serde(default = "default_ndm_api")]
+    pub ndm_api: String,
+    #[serde(default = "default_rci_host")]
+    pub rci_host: String,
+    #[serde(default = "default_rci_port")]
+    pub rci_port: u16,
+    /// ID of the tunnel that owns the NDM default route.
+    #[serde(default = "default_route_owner")]
+    pub default_route_owner: String,
 }
 
 impl Default for RoutingSettings {
@@ -129,10 +182,508 @@ impl Default for RoutingSettings {
             watchdog_failures: default_watchdog_failures(),
             watchdog_check_url: default_watchdog_check_url(),
             watchdog_check_timeout: default_watchdog_check_timeout(),
+            watchdog_probes: Vec::new(),
+            watchdog_quorum: default_watchdog_quorum(),
+            watchdog_wan_check: true,
+            watchdog_ladder: Vec::new(),
+            quality: QualitySettings::default(),
+            sessions: SessionSettings::default(),
+            quota: QuotaSettings::default(),
+            journal_path: default_journal_path(),
+            resolve_interval: default_resolve_interval(),
+            resolve_servers: default_resolve_servers(),
+            ndm_api: default_ndm_api(),
+            rci_host: default_rci_host(),
+            rci_port: default_rci_port(),
+            default_route_owner: default_route_owner(),
+        }
+    }
+}
+
+/// One watchdog connectivity probe, sent through the tunnel interface.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProbeSettings {
+    /// GET `url`; passes on `expect_status` (any 2xx/3xx when unset) and,
+    /// if set, when the body contains `body_contains`.
+    Http {
+        url: String,
+        #[serde(default)]
+        expect_status: Option<u16>,
+        #[serde(default)]
+        body_contains: String,
+    },
+    /// A query for `name` to `server` (`ip:port`).
+    Dns {
+        name: String,
+        #[serde(default = "default_probe_dns_server")]
+        server: String,
+    },
+    /// TCP connect to `address` (`host:port`).
+    Tcp { address: String },
+    /// One ICMP echo to `host`.
+    Icmp { host: String },
+}
+
+/// Latency, loss and jitter sampling through the tunnel on every watchdog
+/// check, kept at minute resolution for 24 hours.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualitySettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Hosts pinged through the tunnel.
+    #[serde(default = "default_quality_targets")]
+    pub targets: Vec<String>,
+    /// Echo requests per target and sample.
+    #[serde(default = "default_quality_pings")]
+    pub pings: u32,
+    /// Seconds between samples, at most 60 so every minute gets one.
+    #[serde(default = "default_quality_interval")]
+    pub interval: u64,
+    /// File the series is saved to once a minute; empty keeps it in memory only.
+    #[serde(default)]
+    pub persist_path: String,
+    /// Loss (percent) that fails the watchdog check when sustained for
+    /// `loss_failure_minutes`; 0 disables.
+    #[serde(default)]
+    pub loss_failure_pct: u32,
+    #[serde(default = "default_quality_loss_minutes")]
+    pub loss_failure_minutes: u32,
+}
+
+impl Default for QualitySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            targets: default_quality_targets(),
+            pings: default_quality_pings(),
+            interval: default_quality_interval(),
+            persist_path: String::new(),
+            loss_failure_pct: 0,
+            loss_failure_minutes: default_quality_loss_minutes(),
+        }
+    }
+}
+
+/// Append-only history of client sessions, one JSON line each.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSettings {
+    /// Empty disables the history.
+    #[serde(default = "default_sessions_path")]
+    pub path: String,
+    #[serde(
+        default = "default_sessions_rotate_size",
+        deserialize_with = "deserialize_size"
+    )]
+    pub rotate_size: u64,
+    #[serde(default = "default_sessions_rotate_keep")]
+    pub rotate_keep: usize,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            path: default_sessions_path(),
+            rotate_size: default_sessions_rotate_size(),
+            rotate_keep: default_sessions_rotate_keep(),
+        }
+    }
+}
+
+/// Data allowance per period, counted over both directions of the tunnel
+/// interface.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Allowance in bytes per period; 0 disables the quota.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub limit: u64,
+    #[serde(default)]
+    pub period: QuotaPeriod,
+    /// Day of month (1-28, UTC) a monthly period starts on.
+    #[serde(default = "default_quota_reset_day")]
+    pub reset_day: u32,
+    /// Usage percentages that log a warning once per period.
+    #[serde(default = "default_quota_warn_pct")]
+    pub warn_pct: Vec<u32>,
+    #[serde(default)]
+    pub action: QuotaAction,
+    /// Usage is saved here so it survives restarts and reboots.
+    #[serde(default = "default_quota_persist_path")]
+    pub persist_path: String,
+}
+
+impl Default for QuotaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            limit: 0,
+            period: QuotaPeriod::default(),
+            reset_day: default_quota_reset_day(),
+            warn_pct: default_quota_warn_pct(),
+            action: QuotaAction::default(),
+            persist_path: default_quota_persist_path(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPeriod {
+    Daily,
+    #[default]
+    Monthly,
+}
+
+/// What happens once the allowance is used up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaAction {
+    /// Disconnect the tunnel as if requested through the API.
+    #[default]
+    Disconnect,
+    /// Stop the client but keep the tunnel wanted, so the kill switch keeps
+    /// blocking LAN traffic; reconnects when the period resets.
+    Block,
+    /// Only log and record an event.
+    Log,
+}
+
+impl std::fmt::Display for QuotaAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            QuotaAction::Disconnect => "disconnect",
+            QuotaAction::Block => "block",
+            QuotaAction::Log => "log",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One step of the watchdog escalation ladder: `action` is taken after
+/// `failures` consecutive failed checks on this rung.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LadderRung {
+    pub action: RecoveryAction,
+    #[serde(default = "default_rung_failures")]
+    pub failures: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecoveryAction {
+    /// Re-add server host routes and the tunnel's NDM routes.
+    ReassertRoutes,
+    /// Re-apply address, MTU and security settings to the NDM interface.
+    ReapplyNdm,
+    /// Restart the client, keeping the NDM interface.
+    RestartClient,
+    /// Restart the client after a full routing teardown.
+    ResetRouting,
+    /// Move the first endpoint address to the end and restart the client.
+    SwitchEndpoint,
+}
+
+impl std::fmt::Display for RecoveryAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RecoveryAction::ReassertRoutes => "reassert_routes",
+            RecoveryAction::ReapplyNdm => "reapply_ndm",
+            RecoveryAction::RestartClient => "restart_client",
+            RecoveryAction::ResetRouting => "reset_routing",
+            RecoveryAction::SwitchEndpoint => "switch_endpoint",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Named CIDR lists merged into the client's included/excluded routes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteListSettings {
+    #[serde(default = "default_route_list_refresh_interval")]
+    pub refresh_interval: u64,
+    #[serde(default = "default_route_list_cache_dir")]
+    pub cache_dir: String,
+    #[serde(default)]
+    pub sources: Vec<RouteListSource>,
+}
+
+/// A single route list: either a local `file` or an HTTP(S) `url`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteListSource {
+    pub name: String,
+    #[serde(default)]
+    pub file: String,
+    #[serde(default)]
+    pub url: String,
+    /// `included` or `excluded`.
+    #[serde(default = "default_route_list_target")]
+    pub target: String,
+    /// ID of the tunnel whose routes the list extends.
+    #[serde(default = "default_route_list_tunnel")]
+    pub tunnel: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+impl Default for RouteListSettings {
+    fn default() -> Self {
+        Self {
+            refresh_interval: default_route_list_refresh_interval(),
+            cache_dir: default_route_list_cache_dir(),
+            sources: Vec::new(),
+        }
+    }
+}
+
+/// Interface and file names of this wrapper instance. Empty names are
+/// derived from `index`: `tun{N}`, `opkgtun{N}`, `opkgbak{N}`, `OpkgTun{N}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InstanceSettings {
+    #[serde(default)]
+    pub index: u32,
+    #[serde(default)]
+    pub tun_name: String,
+    #[serde(default)]
+    pub opkg_tun_name: String,
+    #[serde(default)]
+    pub opkg_backup_name: String,
+    #[serde(default)]
+    pub ndm_name: String,
+    #[serde(default)]
+    pub client_toml: String,
+    #[serde(default)]
+    pub client_pidfile: String,
+}
+
+/// A tunnel besides `main`, with its own client, interfaces and journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtraTunnel {
+    pub id: String,
+    #[serde(default)]
+    pub tunnel: TunnelSettings,
+    /// `index` 0 means "derive from the position in `tunnels`".
+    #[serde(default)]
+    pub instance: InstanceSettings,
+    /// Empty derives `<routing.journal_path>` with a `-<id>` suffix.
+    #[serde(default)]
+    pub journal_path: String,
+}
+
+/// Router-level kill switch: blocks LAN forwarding while the default-route
+/// tunnel should be up but is not connected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillSwitchSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// LAN interfaces whose forwarded traffic is blocked.
+    #[serde(default = "default_killswitch_lan_interfaces")]
+    pub lan_interfaces: Vec<String>,
+    /// Extra CIDRs LAN hosts may still reach.
+    #[serde(default)]
+    pub allow_destinations: Vec<String>,
+}
+
+impl Default for KillSwitchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lan_interfaces: default_killswitch_lan_interfaces(),
+            allow_destinations: Vec::new(),
         }
     }
 }
 
+/// Router DNS through the tunnel. With `mode = "ndm"` the default-route
+/// tunnel binds `servers` to its NDM interface as `ip name-server` entries
+/// while connected; the previous static entries are restored on teardown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsSettings {
+    /// `off` or `ndm`.
+    #[serde(default = "default_dns_mode")]
+    pub mode: String,
+    #[serde(default = "default_dns_servers")]
+    pub servers: Vec<String>,
+    /// Remove the router's other static name-servers while connected.
+    #[serde(default = "default_true")]
+    pub replace_static: bool,
+    /// Name resolved by the leak check.
+    #[serde(default = "default_dns_probe_name")]
+    pub probe_name: String,
+    /// Resolver the leak check queries (the router's DNS proxy).
+    #[serde(default = "default_dns_probe_resolver")]
+    pub probe_resolver: String,
+}
+
+impl Default for DnsSettings {
+    fn default() -> Self {
+        Self {
+            mode: default_dns_mode(),
+            servers: default_dns_servers(),
+            replace_static: true,
+            probe_name: default_dns_probe_name(),
+            probe_resolver: default_dns_probe_resolver(),
+        }
+    }
+}
+
+impl DnsSettings {
+    pub fn is_ndm(&self) -> bool {
+        self.mode.eq_ignore_ascii_case("ndm")
+    }
+}
+
+/// What the daemon does with each tunnel when it starts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StartupSettings {
+    #[serde(default)]
+    pub autostart: AutostartPolicy,
+    /// File the state requested through the API is kept in.
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+    /// Wait for a WAN route, NDM and a set clock before the first connect.
+    #[serde(default = "default_true")]
+    pub boot_wait: bool,
+    /// Give up waiting after this many seconds and connect anyway.
+    #[serde(default = "default_boot_timeout")]
+    pub boot_timeout: u64,
+    /// What to do with a client left running by a previous run.
+    #[serde(default)]
+    pub orphan_client: OrphanPolicy,
+    /// PID file locked for the life of the wrapper; also keeps a second
+    /// instance from starting.
+    #[serde(default = "default_pidfile")]
+    pub pidfile: String,
+    /// File mode creation mask of the daemon, octal.
+    #[serde(default = "default_umask")]
+    pub umask: String,
+}
+
+impl Default for StartupSettings {
+    fn default() -> Self {
+        Self {
+            autostart: AutostartPolicy::default(),
+            state_path: default_state_path(),
+            boot_wait: true,
+            boot_timeout: default_boot_timeout(),
+            orphan_client: OrphanPolicy::default(),
+            pidfile: default_pidfile(),
+            umask: default_umask(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutostartPolicy {
+    /// Connect every tunnel with a configured endpoint.
+    Always,
+    /// Stay disconnected until connected through the API.
+    Never,
+    /// Return to the state last requested through the API; connect when
+    /// nothing was requested yet.
+    #[default]
+    Restore,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanPolicy {
+    /// Keep it running and monitor it as if this run had started it.
+    #[default]
+    Adopt,
+    /// Terminate it before a new client is spawned.
+    Kill,
+}
+
+fn default_state_path() -> String {
+    "/opt/var/lib/trusttunnel/state.json".into()
+}
+fn default_pidfile() -> String {
+    "/opt/var/run/trusttunnel-keenetic.pid".into()
+}
+fn default_umask() -> String {
+    "022".into()
+}
+fn default_boot_timeout() -> u64 {
+    180
+}
+
+/// Weekly time windows that connect and disconnect tunnels.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Fixed local time offset from UTC, `+HH:MM` or `-HH:MM`; empty
+    /// follows `timezone`.
+    #[serde(default)]
+    pub utc_offset: String,
+    /// POSIX `TZ` value such as `CET-1CEST,M3.5.0,M10.5.0/3`; empty uses
+    /// the process `TZ` or the system zone.
+    #[serde(default)]
+    pub timezone: String,
+    /// Tunnel IDs driven by the schedule; empty means all.
+    #[serde(default)]
+    pub tunnels: Vec<String>,
+    /// State outside every window.
+    #[serde(default)]
+    pub default_state: ScheduleState,
+    /// The first window containing the current time decides the state.
+    #[serde(default)]
+    pub windows: Vec<ScheduleWindow>,
+}
+
+/// `start`-`end` (`HH:MM`, local time) on `days`; an `end` before `start`
+/// runs past midnight into the next day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleWindow {
+    /// `mon` … `sun` or ranges like `mon-fri`; empty means every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+    pub state: ScheduleState,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleState {
+    #[default]
+    Up,
+    Down,
+}
+
+fn default_dns_mode() -> String {
+    "off".into()
+}
+fn default_dns_servers() -> Vec<String> {
+    vec!["1.1.1.1".into(), "9.9.9.9".into()]
+}
+fn default_dns_probe_name() -> String {
+    "whoami.akamai.net".into()
+}
+fn default_dns_probe_resolver() -> String {
+    "127.0.0.1:53".into()
+}
+
+fn default_killswitch_lan_interfaces() -> Vec<String> {
+    vec!["br0".into()]
+}
+
+fn default_route_list_refresh_interval() -> u64 {
+    6 * 3600
+}
+fn default_route_list_cache_dir() -> String {
+    "/opt/var/cache/trusttunnel/route-lists".into()
+}
+fn default_route_list_target() -> String {
+    "included".into()
+}
+fn default_route_list_tunnel() -> String {
+    "main".into()
+}
+
 fn default_watchdog_interval() -> u64 {
     30
 }
@@ -142,9 +693,69 @@ fn default_watchdog_failures() -> u32 {
 fn default_watchdog_check_url() -> String {
     "http://connectivitycheck.gstatic.com/generate_204".into()
 }
+fn default_watchdog_quorum() -> u32 {
+    1
+}
+fn default_quality_targets() -> Vec<String> {
+    vec!["1.1.1.1".into(), "8.8.8.8".into()]
+}
+fn default_quality_pings() -> u32 {
+    5
+}
+fn default_quality_interval() -> u64 {
+    30
+}
+fn default_quality_loss_minutes() -> u32 {
+    5
+}
+fn default_sessions_path() -> String {
+    "/opt/var/lib/trusttunnel/sessions.jsonl".into()
+}
+fn default_sessions_rotate_size() -> u64 {
+    256 * 1024
+}
+fn default_sessions_rotate_keep() -> usize {
+    3
+}
+fn default_quota_reset_day() -> u32 {
+    1
+}
+fn default_quota_warn_pct() -> Vec<u32> {
+    vec![80, 95]
+}
+fn default_quota_persist_path() -> String {
+    "/opt/var/lib/trusttunnel/quota.json".into()
+}
+fn default_rung_failures() -> u32 {
+    1
+}
+fn default_probe_dns_server() -> String {
+    "1.1.1.1:53".into()
+}
 fn default_watchdog_check_timeout() -> u64 {
     5
 }
+fn default_ndm_api() -> String {
+    "auto".into()
+}
+fn default_rci_host() -> String {
+    "127.0.0.1".into()
+}
+fn default_route_owner() -> String {
+    "main".into()
+}
+fn default_rci_port() -> u16 {
+    79
+}
+fn default_resolve_interval() -> u64 {
+    300
+}
+fn default_resolve_servers() -> Vec<String> {
+    vec!["1.1.1.1:53".into(), "8.8.8.8:53".into()]
+}
+fn default_journal_path() -> String {
+    "/opt/var/lib/trusttunnel/routing-journal.json".into()
+}
 
 fn default_upstream_protocol() -> String {
     "http2".into()
@@ -220,28 +831,29 @@ fn parse_size_with_units(value: &str) -> Option<u64> {
         "K" | "KB" => 1024u64,
         "M" | "MB" => 1024u64 * 1024,
         "G" | "GB" => 1024u64 * 1024 * 1024,
+        "T" | "TB" => 1024u64 * 1024 * 1024 * 1024,
         "B" | "" => 1u64,
         _ => return None,
     };
     number.checked_mul(multiplier)
 }
 
-fn deserialize_rotate_size<'de, D>(deserializer: D) -> Result<u64, D::Error>
+fn deserialize_size<'de, D>(deserializer: D) -> Result<u64, D::Error>
 where
     D: Deserializer<'de>,
 {
     #[derive(Deserialize)]
     #[serde(untagged)]
-    enum RotateSize {
+    enum Size {
         Number(u64),
         Text(String),
     }
 
-    match RotateSize::deserialize(deserializer)? {
-        RotateSize::Number(v) => Ok(v),
-        RotateSize::Text(s) => parse_size_with_units(&s).ok_or_else(|| {
+    match Size::deserialize(deserializer)? {
+        Size::Number(v) => Ok(v),
+        Size::Text(s) => parse_size_with_units(&s).ok_or_else(|| {
             de::Error::custom(format!(
-                "invalid rotate_size '{}', use bytes (1048576) or units (512KB, 10MB, 1GB)",
+                "invalid size '{}', use bytes (1048576) or units (512KB, 10MB, 1GB)",
                 s
             ))
         }),
@@ -330,8 +942,9 @@ impl WrapperConfig {
     }
 }
 
-/// Generates a valid TOML config file for `trusttunnel_client`.
-pub fn generate_client_toml(settings: &TunnelSettings) -> String {
+/// Generates a valid TOML config file for `trusttunnel_client`. `tun_name`
+/// is the device the client creates, so instances do not share `tun0`.
+pub fn generate_client_toml(settings: &TunnelSettings, tun_name: &str) -> String {
     let mut toml = String::with_capacity(1024);
 
     toml.push_str(&format!("loglevel = \"{}\"\n", settings.loglevel));
@@ -404,6 +1017,7 @@ pub fn generate_client_toml(settings: &TunnelSettings) -> String {
     }
 
     toml.push_str("\n[listener.tun]\n");
+    toml.push_str(&format!("name = \"{}\"\n", tun_name));
     toml.push_str(&format!("bound_if = \"{}\"\n", settings.bound_if));
     toml.push_str(&format!(
         "change_system_dns = {}\n",
@@ -464,11 +1078,11 @@ mod tests {
             password: "pass".into(),
             ..Default::default()
         };
-        let toml = generate_client_toml(&s);
+        let toml = generate_client_toml(&s, "tun0");
         assert!(toml.contains("[endpoint]"));
         assert!(toml.contains("hostname = \"vpn.example.com\""));
         assert!(toml.contains("username = \"user\""));
-        assert!(toml.contains("[listener.tun]"));
+        assert!(toml.contains("[listener.tun]\nname = \"tun0\"\n"));
     }
 
     #[test]
@@ -477,6 +1091,7 @@ mod tests {
         assert_eq!(parse_size_with_units("512KB"), Some(512 * 1024));
         assert_eq!(parse_size_with_units("10mb"), Some(10 * 1024 * 1024));
         assert_eq!(parse_size_with_units("1 G"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size_with_units("2TB"), Some(2 << 40));
         assert_eq!(parse_size_with_units(""), None);
         assert_eq!(parse_size_with_units("oops"), None);
     }
@@ -0,0 +1,125 @@
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use nix::unistd::{fork, setsid, ForkResult};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+/// Lets the daemon report the end of its startup to the original process
+/// waiting in `daemonize`, which exits with the result.
+pub struct Readiness {
+    pipe: Option<File>,
+}
+
+impl Readiness {
+    /// Nothing waits when running in the foreground.
+    pub fn foreground() -> Self {
+        Self { pipe: None }
+    }
+
+    /// Startup finished; the waiting process exits with 0.
+    pub fn ready(&mut self) {
+        self.send("ok");
+    }
+
+    /// Startup failed; the waiting process prints `msg` and exits with 1.
+    pub fn fail(&mut self, msg: &str) {
+        self.send(&format!("error: {}", msg));
+    }
+
+    fn send(&mut self, line: &str) {
+        if let Some(mut pipe) = self.pipe.take() {
+            let _ = writeln!(pipe, "{}", line);
+        }
+    }
+}
+
+/// `umask` setting as an octal mode, e.g. `"022"`.
+pub fn parse_umask(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim(), 8)
+        .ok()
+        .filter(|mask| *mask <= 0o777)
+        .ok_or_else(|| format!("invalid umask '{}', use an octal mode like 022", s))
+}
+
+/// Detach with a double fork. The first child starts a new session and
+/// forks again, so the daemon is not a session leader and can never get a
+/// controlling terminal back. The original process waits until the daemon
+/// reports through the returned `Readiness` and exits with its result.
+pub fn daemonize(umask: u32) -> Readiness {
+    // Close-on-exec, so clients spawned during startup do not keep the
+    // pipe open.
+    let (read_end, write_end) = match nix::unistd::pipe2(OFlag::O_CLOEXEC) {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            eprintln!("Cannot daemonize: pipe failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child }) => {
+            drop(write_end);
+            let _ = nix::sys::wait::waitpid(child, None);
+            wait_for_daemon(File::from(read_end));
+        }
+        Ok(ForkResult::Child) => drop(read_end),
+        Err(e) => {
+            eprintln!("Cannot daemonize: fork failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let mut readiness = Readiness {
+        pipe: Some(File::from(write_end)),
+    };
+    let _ = setsid();
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { .. }) => std::process::exit(0),
+        Ok(ForkResult::Child) => {}
+        Err(e) => {
+            readiness.fail(&format!("second fork failed: {}", e));
+            std::process::exit(1);
+        }
+    }
+
+    let _ = nix::unistd::chdir("/");
+    nix::sys::stat::umask(Mode::from_bits_truncate(umask));
+    if let Ok(devnull) = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+    {
+        use std::os::unix::io::AsRawFd;
+        let fd = devnull.as_raw_fd();
+        let _ = nix::unistd::dup2(fd, 0);
+        let _ = nix::unistd::dup2(fd, 1);
+        let _ = nix::unistd::dup2(fd, 2);
+    }
+    readiness
+}
+
+fn wait_for_daemon(pipe: File) -> ! {
+    let mut line = String::new();
+    let _ = BufReader::new(pipe).read_line(&mut line);
+    let line = line.trim();
+    if line == "ok" {
+        std::process::exit(0);
+    }
+    match line.strip_prefix("error: ") {
+        Some(msg) => eprintln!("Failed to start: {}", msg),
+        None => eprintln!("Failed to start: daemon exited during startup"),
+    }
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_umask() {
+        assert_eq!(parse_umask("022"), Ok(0o022));
+        assert_eq!(parse_umask("0077"), Ok(0o077));
+        assert!(parse_umask("089").is_err());
+        assert!(parse_umask("1777").is_err());
+    }
+}
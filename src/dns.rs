@@ -0,0 +1,343 @@
+use crate::config::DnsSettings;
+use crate::journal::Journal;
+use crate::ndm::{self, NameServer, NdmCommand};
+use crate::routing::{self, InterfaceNames};
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// Bind `settings.servers` to the tunnel's NDM interface and, with
+/// `replace_static`, remove the other static name-servers. Every change is
+/// journaled, so a full teardown or crash recovery restores the originals.
+/// Domain-specific servers are left alone.
+pub fn apply(
+    names: &InterfaceNames,
+    settings: &DnsSettings,
+    journal: &Mutex<Journal>,
+) -> Result<(), String> {
+    let mut added = 0;
+    for address in settings.servers.iter().map(|a| a.trim()) {
+        if address.is_empty() {
+            continue;
+        }
+        let server = NameServer {
+            address: address.to_string(),
+            domain: None,
+            interface: Some(names.ndm.clone()),
+        };
+        match routing::ndm_journaled(
+            NdmCommand::NameServer(server.clone()),
+            NdmCommand::NoNameServer(server),
+            journal,
+        ) {
+            Ok(()) => added += 1,
+            Err(e) => log::warn!("[dns] cannot add name-server {}: {}", address, e),
+        }
+    }
+    if added == 0 {
+        return Err("no DNS server could be bound to the tunnel".into());
+    }
+
+    if settings.replace_static {
+        let config = ndm::running_config().map_err(|e| e.to_string())?;
+        for server in config.iter().filter_map(|l| NameServer::parse(l)) {
+            if server.domain.is_some() || server.interface.as_deref() == Some(names.ndm.as_str()) {
+                continue;
+            }
+            routing::ndm_journaled(
+                NdmCommand::NoNameServer(server.clone()),
+                NdmCommand::NameServer(server),
+                journal,
+            )?;
+        }
+    }
+
+    let msg = format!("[dns] {} name-server(s) bound to {}", added, &names.ndm);
+    log::info!("{}", msg);
+    crate::logs::global_buffer().push(msg);
+    Ok(())
+}
+
+/// Path the kernel takes to one DNS server used by the DNS proxy.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerPath {
+    pub address: String,
+    pub dev: String,
+    pub via_tunnel: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LeakReport {
+    pub mode: String,
+    pub probe_name: String,
+    pub resolver: String,
+    /// A records returned for the probe; for `whoami`-style names this is
+    /// the address of the recursive resolver that asked on our behalf.
+    pub answers: Vec<String>,
+    pub servers: Vec<ServerPath>,
+    /// `tunnel`, `wan`, `mixed` or `unknown`.
+    pub path: String,
+    pub leak: bool,
+    pub errors: Vec<String>,
+}
+
+/// Resolve the probe name through the router's DNS proxy and report which
+/// interface its upstream servers are reached over.
+pub fn leak_check(names: &InterfaceNames, settings: &DnsSettings) -> LeakReport {
+    let mut report = LeakReport {
+        mode: settings.mode.clone(),
+        probe_name: settings.probe_name.clone(),
+        resolver: settings.probe_resolver.clone(),
+        ..Default::default()
+    };
+
+    match settings.probe_resolver.parse::<SocketAddr>() {
+        Ok(resolver) => match resolve_a(resolver, &settings.probe_name, PROBE_TIMEOUT, None) {
+            Ok(answers) => report.answers = answers.iter().map(|a| a.to_string()).collect(),
+            Err(e) => report.errors.push(format!("probe: {}", e)),
+        },
+        Err(e) => report.errors.push(format!(
+            "probe_resolver '{}': {}",
+            settings.probe_resolver, e
+        )),
+    }
+
+    let bound: Vec<String> = ndm::running_config()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|l| NameServer::parse(l))
+        .filter(|s| s.interface.as_deref() == Some(names.ndm.as_str()))
+        .map(|s| s.address)
+        .collect();
+    match ndm::show_name_servers() {
+        Ok(addresses) => {
+            for address in addresses {
+                let dev = address
+                    .parse::<IpAddr>()
+                    .ok()
+                    .or_else(|| address.parse::<SocketAddr>().ok().map(|s| s.ip()))
+                    .and_then(routing::route_dev)
+                    .unwrap_or_default();
+                let via_tunnel = dev == names.opkg_tun || bound.contains(&address);
+                report.servers.push(ServerPath {
+                    address,
+                    dev,
+                    via_tunnel,
+                });
+            }
+        }
+        Err(e) => report.errors.push(format!("show ip name-server: {}", e)),
+    }
+
+    let tunneled = report.servers.iter().filter(|s| s.via_tunnel).count();
+    report.path = match (tunneled, report.servers.len()) {
+        (_, 0) => "unknown",
+        (t, n) if t == n => "tunnel",
+        (0, _) => "wan",
+        _ => "mixed",
+    }
+    .to_string();
+    report.leak = tunneled < report.servers.len();
+    report
+}
+
+/// Send one A query to `resolver`, optionally out of `dev`, and return the
+/// addresses in the answer.
+pub fn resolve_a(
+    resolver: SocketAddr,
+    name: &str,
+    timeout: Duration,
+    dev: Option<&str>,
+) -> Result<Vec<Ipv4Addr>, String> {
+    let addrs = query(resolver, name, TYPE_A, timeout, dev)?;
+    Ok(addrs
+        .into_iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(v4) => Some(v4),
+            IpAddr::V6(_) => None,
+        })
+        .collect())
+}
+
+/// A and AAAA records of `name` from `resolver`, optionally out of `dev`.
+/// Each query waits at most `timeout`; a failed AAAA query is ignored.
+pub fn resolve_host(
+    resolver: SocketAddr,
+    name: &str,
+    timeout: Duration,
+    dev: Option<&str>,
+) -> Result<Vec<IpAddr>, String> {
+    let mut addrs = query(resolver, name, TYPE_A, timeout, dev)?;
+    match query(resolver, name, TYPE_AAAA, timeout, dev) {
+        Ok(v6) => addrs.extend(v6),
+        Err(e) => log::debug!("[dns] AAAA {} via {}: {}", name, resolver, e),
+    }
+    Ok(addrs)
+}
+
+fn query(
+    resolver: SocketAddr,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+    dev: Option<&str>,
+) -> Result<Vec<IpAddr>, String> {
+    // The answers become server host routes, so make them hard to spoof:
+    // a random ID, replies only from the resolver, and the question echoed.
+    let [hi, lo, ..] = *uuid::Uuid::new_v4().as_bytes();
+    let query = build_query(u16::from_be_bytes([hi, lo]), name, qtype)?;
+    let bind = if resolver.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(bind).map_err(|e| format!("bind: {}", e))?;
+    if let Some(dev) = dev {
+        crate::probes::bind_to_device(&socket, dev)?;
+    }
+    socket
+        .set_read_timeout(Some(timeout))
+        .map_err(|e| e.to_string())?;
+    socket
+        .connect(resolver)
+        .map_err(|e| format!("connect to {}: {}", resolver, e))?;
+    socket
+        .send(&query)
+        .map_err(|e| format!("send to {}: {}", resolver, e))?;
+    let mut buf = [0u8; 1500];
+    let len = socket
+        .recv(&mut buf)
+        .map_err(|e| format!("no answer from {}: {}", resolver, e))?;
+    parse_answers(&query, &buf[..len])
+}
+
+fn build_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>, String> {
+    let mut msg = Vec::with_capacity(32 + name.len());
+    msg.extend(id.to_be_bytes());
+    // Recursion desired; one question.
+    msg.extend([0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid name '{}'", name));
+        }
+        msg.push(label.len() as u8);
+        msg.extend(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend(qtype.to_be_bytes());
+    msg.extend(CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+/// Offset just past the (possibly compressed) name starting at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)?;
+        if len & 0xc0 == 0xc0 {
+            return Some(pos + 2);
+        }
+        pos += 1;
+        if len == 0 {
+            return Some(pos);
+        }
+        pos += len as usize;
+    }
+}
+
+/// Addresses in the response `msg` to `query`, which must carry the same ID
+/// and echo the same single question.
+fn parse_answers(query: &[u8], msg: &[u8]) -> Result<Vec<IpAddr>, String> {
+    let u16_at = |pos: usize| -> Option<u16> {
+        Some(u16::from_be_bytes([*msg.get(pos)?, *msg.get(pos + 1)?]))
+    };
+    let truncated = || "truncated DNS response".to_string();
+    if msg.get(..2).ok_or_else(truncated)? != &query[..2] {
+        return Err("DNS response id mismatch".into());
+    }
+    let rcode = u16_at(2).ok_or_else(truncated)? & 0x000f;
+    if rcode != 0 {
+        return Err(format!("DNS error rcode {}", rcode));
+    }
+    let answers = u16_at(6).ok_or_else(truncated)?;
+
+    // Name, type and class as asked; resolvers may change the name's case.
+    let question = &query[12..];
+    let echoed = msg.get(12..12 + question.len()).ok_or_else(truncated)?;
+    if u16_at(4) != Some(1) || !echoed.eq_ignore_ascii_case(question) {
+        return Err("DNS response question mismatch".into());
+    }
+    let mut pos = 12 + question.len();
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(msg, pos).ok_or_else(truncated)?;
+        let rtype = u16_at(pos).ok_or_else(truncated)?;
+        let rdlen = u16_at(pos + 8).ok_or_else(truncated)? as usize;
+        let data = msg.get(pos + 10..pos + 10 + rdlen).ok_or_else(truncated)?;
+        if rtype == TYPE_A && rdlen == 4 {
+            addrs.push(IpAddr::V4(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            )));
+        } else if rtype == TYPE_AAAA && rdlen == 16 {
+            let octets: [u8; 16] = data.try_into().map_err(|_| truncated())?;
+            addrs.push(IpAddr::V6(Ipv6Addr::from(octets)));
+        }
+        pos += 10 + rdlen;
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_answers() {
+        let query = build_query(0x1234, "whoami.akamai.net", TYPE_A).unwrap();
+        let mut resp = query.clone();
+        resp[2] = 0x81;
+        resp[3] = 0x80;
+        resp[7] = 3;
+        // CNAME answer with a compressed owner name, then A and AAAA records.
+        resp.extend([0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 0x0c]);
+        resp.extend([0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 172, 64, 1, 7]);
+        resp.extend([0xc0, 0x0c, 0, 28, 0, 1, 0, 0, 0, 60, 0, 16]);
+        resp.extend([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(
+            parse_answers(&query, &resp).unwrap(),
+            vec![
+                IpAddr::V4(Ipv4Addr::new(172, 64, 1, 7)),
+                "2001:db8::1".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert!(parse_answers(&query, &resp[..resp.len() - 2]).is_err());
+        // Another ID, name or type is not an answer to this query.
+        for other in [
+            build_query(0x4321, "whoami.akamai.net", TYPE_A).unwrap(),
+            build_query(0x1234, "whoami.akamai.com", TYPE_A).unwrap(),
+            build_query(0x1234, "whoami.akamai.net", TYPE_AAAA).unwrap(),
+        ] {
+            assert!(parse_answers(&other, &resp).is_err());
+        }
+        // The case of the echoed name may differ (0x20 encoding).
+        resp[13..19].copy_from_slice(b"WHOAMI");
+        assert!(parse_answers(&query, &resp).is_ok());
+        assert!(build_query(1, "bad..name", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_parse_name_server_line() {
+        let server = NameServer::parse("ip name-server 1.1.1.1 on OpkgTun0").unwrap();
+        assert_eq!(server.interface.as_deref(), Some("OpkgTun0"));
+        assert_eq!(server.domain, None);
+        let split = NameServer::parse("ip name-server 10.0.0.53 corp.example").unwrap();
+        assert_eq!(split.domain.as_deref(), Some("corp.example"));
+        assert_eq!(split.to_string(), "ip name-server 10.0.0.53 corp.example");
+        assert!(NameServer::parse("ip route default OpkgTun0").is_none());
+    }
+}
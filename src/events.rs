@@ -0,0 +1,73 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// One notable tunnel event (recovery step, WAN outage, ...).
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// Unix time, seconds.
+    pub time: u64,
+    pub kind: String,
+    pub message: String,
+}
+
+/// Per-tunnel ring of recent events, newest last.
+pub struct EventHistory {
+    inner: Mutex<VecDeque<Event>>,
+    max_events: usize,
+}
+
+impl EventHistory {
+    pub fn new(max_events: usize) -> Self {
+        Self {
+            inner: Mutex::new(VecDeque::with_capacity(max_events)),
+            max_events: max_events.max(1),
+        }
+    }
+
+    pub fn record(&self, kind: &str, message: String) {
+        let time = crate::util::unix_now();
+        if let Ok(mut events) = self.inner.lock() {
+            events.push_back(Event {
+                time,
+                kind: kind.to_string(),
+                message,
+            });
+            while events.len() > self.max_events {
+                events.pop_front();
+            }
+        }
+    }
+
+    pub fn recent(&self, count: usize) -> Vec<Event> {
+        match self.inner.lock() {
+            Ok(events) => {
+                let skip = events.len().saturating_sub(count);
+                events.iter().skip(skip).cloned().collect()
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_keeps_newest() {
+        let history = EventHistory::new(2);
+        for i in 0..3 {
+            history.record("recovery", format!("step {}", i));
+        }
+        let recent = history.recent(10);
+        assert_eq!(
+            recent
+                .iter()
+                .map(|e| e.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["step 1", "step 2"]
+        );
+        assert_eq!(history.recent(1)[0].message, "step 2");
+    }
+}
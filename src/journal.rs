@@ -0,0 +1,270 @@
+use crate::ndm::NdmCommand;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// One applied routing change. Every variant knows its inverse, which
+/// `routing::undo_change` executes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    /// `interface <name>` created in NDM; undone with `no interface <name>`.
+    NdmInterface { name: String },
+    /// NDM command paired with the command that reverts it.
+    NdmCommand {
+        #[serde(deserialize_with = "ndm_command")]
+        command: NdmCommand,
+        #[serde(deserialize_with = "ndm_command")]
+        undo: NdmCommand,
+    },
+    /// Linux link renamed `from` -> `to`; undone by renaming it back.
+    LinkRename { from: String, to: String },
+    /// Linux link set up; undone by setting it down.
+    LinkUp { name: String },
+    /// `/32` or `/128` host route to a VPN server via `dev`.
+    HostRoute { ip: IpAddr, dev: String },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::NdmInterface { name } => write!(f, "ndm interface {}", name),
+            Change::NdmCommand { command, .. } => write!(f, "ndm '{}'", command),
+            Change::LinkRename { from, to } => write!(f, "link rename {} -> {}", from, to),
+            Change::LinkUp { name } => write!(f, "link {} up", name),
+            Change::HostRoute { ip, dev } => write!(f, "host route {} dev {}", ip, dev),
+        }
+    }
+}
+
+/// Journals written before NDM commands were typed store them as CLI strings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredNdmCommand {
+    Typed(NdmCommand),
+    Cli(String),
+}
+
+fn ndm_command<'de, D>(deserializer: D) -> Result<NdmCommand, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    match StoredNdmCommand::deserialize(deserializer)? {
+        StoredNdmCommand::Typed(cmd) => Ok(cmd),
+        StoredNdmCommand::Cli(line) => NdmCommand::from_cli(&line)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown ndm command '{}'", line))),
+    }
+}
+
+/// Ordered list of applied changes, persisted after every mutation so a
+/// restarted wrapper can undo what a crashed one left behind.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    entries: Vec<Change>,
+}
+
+impl Journal {
+    /// Load the journal from `path`; a missing or corrupt file yields an empty journal.
+    pub fn load(path: &str) -> Self {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                log::warn!("[journal] ignoring unreadable {}: {}", path, e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self {
+            path: PathBuf::from(path),
+            entries,
+        }
+    }
+
+    pub fn entries(&self) -> &[Change] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Append a change. Re-applying an already recorded change is a no-op.
+    pub fn record(&mut self, change: Change) {
+        if self.entries.contains(&change) {
+            return;
+        }
+        self.entries.push(change);
+        self.persist();
+    }
+
+    /// Drop matching entries without undoing them (the change is already gone).
+    pub fn forget<P>(&mut self, pred: P)
+    where
+        P: Fn(&Change) -> bool,
+    {
+        let before = self.entries.len();
+        self.entries.retain(|c| !pred(c));
+        if self.entries.len() != before {
+            self.persist();
+        }
+    }
+
+    /// Undo matching entries newest-first and remove the undone ones from the
+    /// journal. Entries whose undo fails stay recorded, so the next teardown
+    /// or recovery retries them. Returns the entries that were undone.
+    pub fn unwind_where<P, U>(&mut self, pred: P, undo: U) -> Vec<Change>
+    where
+        P: Fn(&Change) -> bool,
+        U: Fn(&Change) -> Result<(), String>,
+    {
+        let mut succeeded = Vec::new();
+        for change in self.entries.iter().rev().filter(|c| pred(c)) {
+            match undo(change) {
+                Ok(()) => {
+                    log::info!("[journal] undone: {}", change);
+                    succeeded.push(change.clone());
+                }
+                Err(e) => log::warn!("[journal] undo of {} failed, keeping it: {}", change, e),
+            }
+        }
+        if !succeeded.is_empty() {
+            self.entries.retain(|c| !succeeded.contains(c));
+            self.persist();
+        }
+        succeeded
+    }
+
+    /// Undo every entry newest-first; see `unwind_where`. Returns the entries
+    /// that were undone.
+    pub fn unwind<U>(&mut self, undo: U) -> Vec<Change>
+    where
+        U: Fn(&Change) -> Result<(), String>,
+    {
+        self.unwind_where(|_| true, undo)
+    }
+
+    fn persist(&self) {
+        if let Err(e) = write_atomic(&self.path, &self.entries) {
+            log::warn!("[journal] failed to persist {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn write_atomic(path: &Path, entries: &[Change]) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(entries).map_err(|e| e.to_string())?;
+    crate::util::atomic_write(path, content).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_unwind_order_and_persistence() {
+        let dir = std::env::temp_dir().join(format!("tt-journal-{}", std::process::id()));
+        let path = dir.join("journal.json");
+        let path_str = path.to_str().unwrap();
+
+        let mut journal = Journal::load(path_str);
+        journal.record(Change::NdmInterface {
+            name: "OpkgTun0".into(),
+        });
+        journal.record(Change::HostRoute {
+            ip: "1.2.3.4".parse().unwrap(),
+            dev: "eth3".into(),
+        });
+        journal.record(Change::NdmInterface {
+            name: "OpkgTun0".into(),
+        });
+        assert_eq!(journal.entries().len(), 2);
+
+        let reloaded = Journal::load(path_str);
+        assert_eq!(reloaded.entries(), journal.entries());
+
+        let undone = Mutex::new(Vec::new());
+        let succeeded = journal.unwind(|c| {
+            undone.lock().unwrap().push(c.to_string());
+            match c {
+                Change::HostRoute { .. } => Err("no such route".into()),
+                _ => Ok(()),
+            }
+        });
+        assert_eq!(
+            undone.into_inner().unwrap(),
+            vec!["host route 1.2.3.4 dev eth3", "ndm interface OpkgTun0"]
+        );
+        assert_eq!(
+            succeeded,
+            vec![Change::NdmInterface {
+                name: "OpkgTun0".into()
+            }]
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_failed_undo_stays_in_journal() {
+        let dir = std::env::temp_dir().join(format!("tt-journal-retry-{}", std::process::id()));
+        let path = dir.join("journal.json");
+        let path_str = path.to_str().unwrap();
+
+        let route = Change::HostRoute {
+            ip: "1.2.3.4".parse().unwrap(),
+            dev: "eth3".into(),
+        };
+        let mut journal = Journal::load(path_str);
+        journal.record(Change::NdmInterface {
+            name: "OpkgTun0".into(),
+        });
+        journal.record(route.clone());
+
+        journal.unwind(|c| match c {
+            Change::HostRoute { .. } => Err("RCI not ready".into()),
+            _ => Ok(()),
+        });
+        assert_eq!(journal.entries(), std::slice::from_ref(&route));
+        assert_eq!(
+            Journal::load(path_str).entries(),
+            std::slice::from_ref(&route)
+        );
+
+        // A later teardown retries it.
+        assert_eq!(journal.unwind(|_| Ok(())), vec![route]);
+        assert!(Journal::load(path_str).is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_load_legacy_string_commands() {
+        let dir = std::env::temp_dir().join(format!("tt-journal-legacy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("journal.json");
+        std::fs::write(
+            &path,
+            r#"[
+  {"kind": "ndm_interface", "name": "OpkgTun0"},
+  {"kind": "ndm_command", "command": "interface OpkgTun0 up", "undo": "interface OpkgTun0 down"},
+  {"kind": "ndm_command", "command": "ipv6 route default OpkgTun0", "undo": "no ipv6 route default OpkgTun0"}
+]"#,
+        )
+        .unwrap();
+
+        let journal = Journal::load(path.to_str().unwrap());
+        assert_eq!(journal.entries().len(), 3);
+        assert_eq!(
+            journal.entries()[2],
+            Change::NdmCommand {
+                command: NdmCommand::DefaultRoute {
+                    name: "OpkgTun0".into(),
+                    ipv6: true,
+                },
+                undo: NdmCommand::NoDefaultRoute {
+                    name: "OpkgTun0".into(),
+                    ipv6: true,
+                },
+            }
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::config::KillSwitchSettings;
+use std::net::IpAddr;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const CHAIN: &str = "TT_KILLSWITCH";
+/// NDM rebuilds netfilter tables on interface changes; while engaged the
+/// FORWARD jump is re-checked this often and restored if it vanished.
+const VERIFY_INTERVAL: Duration = Duration::from_secs(10);
+const FAMILIES: [(&str, bool); 2] = [("iptables", false), ("ip6tables", true)];
+
+/// Router-level kill switch built from an iptables/ip6tables chain hooked
+/// into FORWARD.
+pub struct KillSwitch {
+    settings: KillSwitchSettings,
+    /// Linux name of the guarded tunnel, the one output LAN traffic may use.
+    tunnel_dev: String,
+    /// Present while the rules are installed.
+    engaged: Mutex<Option<Installed>>,
+}
+
+struct Installed {
+    /// Chain rules per entry of `FAMILIES`, as last installed.
+    rules: Vec<Vec<Vec<String>>>,
+    checked: Instant,
+}
+
+impl KillSwitch {
+    pub fn new(settings: &KillSwitchSettings, tunnel_dev: &str) -> Self {
+        Self {
+            settings: settings.clone(),
+            tunnel_dev: tunnel_dev.to_string(),
+            engaged: Mutex::new(None),
+        }
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.engaged.lock().unwrap().is_some()
+    }
+
+    /// Install the blocking rules. While engaged, the chain is rebuilt as
+    /// soon as `server_ips` or `allow_ports` change (the server IPs are
+    /// usually resolved only after the first engage), and the FORWARD jumps
+    /// of both families are re-verified at most every `VERIFY_INTERVAL`.
+    pub fn engage(&self, server_ips: &[IpAddr], allow_ports: &[u16]) {
+        let rules: Vec<_> = FAMILIES
+            .iter()
+            .map(|&(_, ipv6)| {
+                build_rules(
+                    &self.settings,
+                    &self.tunnel_dev,
+                    server_ips,
+                    allow_ports,
+                    ipv6,
+                )
+            })
+            .collect();
+        let mut engaged = self.engaged.lock().unwrap();
+        if let Some(installed) = engaged.as_mut() {
+            if installed.rules != rules {
+                log::info!("[killswitch] allowed servers or ports changed, rebuilding");
+            } else if installed.checked.elapsed() < VERIFY_INTERVAL {
+                return;
+            } else {
+                installed.checked = Instant::now();
+                let hooked = FAMILIES
+                    .iter()
+                    .all(|&(bin, _)| iptables(bin, &["-C", "FORWARD", "-j", CHAIN]).is_ok());
+                if hooked {
+                    return;
+                }
+                log::warn!("[killswitch] FORWARD jump disappeared, re-installing");
+            }
+        }
+
+        for (&(bin, _), rules) in FAMILIES.iter().zip(&rules) {
+            if let Err(e) = install(bin, rules) {
+                let msg = format!("[killswitch] {} install failed: {}", bin, e);
+                log::warn!("{}", msg);
+                crate::logs::global_buffer().push(msg);
+            }
+        }
+        if engaged.is_none() {
+            let msg = format!(
+                "[killswitch] engaged: blocking forwarding from {}",
+                self.settings.lan_interfaces.join(", ")
+            );
+            log::warn!("{}", msg);
+            crate::logs::global_buffer().push(msg);
+        }
+        *engaged = Some(Installed {
+            rules,
+            checked: Instant::now(),
+        });
+    }
+
+    /// Remove the rules. Safe to call when nothing is installed, which also
+    /// clears a chain left behind by a previous run.
+    pub fn release(&self) {
+        let mut engaged = self.engaged.lock().unwrap();
+        for (bin, _) in FAMILIES {
+            while iptables(bin, &["-D", "FORWARD", "-j", CHAIN]).is_ok() {}
+            let _ = iptables(bin, &["-F", CHAIN]);
+            let _ = iptables(bin, &["-X", CHAIN]);
+        }
+        if engaged.take().is_some() {
+            log::info!("[killswitch] released");
+            crate::logs::global_buffer().push("[killswitch] released".into());
+        }
+    }
+}
+
+fn iptables(bin: &str, args: &[&str]) -> Result<(), String> {
+    let output = Command::new(bin)
+        .args(args)
+        .output()
+        .map_err(|e| format!("{} {:?}: {}", bin, args, e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} {:?}: {}",
+            bin,
+            args,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// (Re)create the chain with `rules` and hook it at the top of FORWARD.
+fn install(bin: &str, rules: &[Vec<String>]) -> Result<(), String> {
+    let _ = iptables(bin, &["-N", CHAIN]);
+    iptables(bin, &["-F", CHAIN])?;
+    for rule in rules {
+        let mut args = vec!["-A", CHAIN];
+        args.extend(rule.iter().map(String::as_str));
+        iptables(bin, &args)?;
+    }
+    if iptables(bin, &["-C", "FORWARD", "-j", CHAIN]).is_err() {
+        iptables(bin, &["-I", "FORWARD", "1", "-j", CHAIN])?;
+    }
+    Ok(())
+}
+
+fn is_v6_cidr(cidr: &str) -> bool {
+    cidr.contains(':')
+}
+
+/// Chain rules for one address family: LAN traffic may go to tunnels, other
+/// LAN interfaces and allowlisted destinations/ports; everything else is rejected.
+fn build_rules(
+    settings: &KillSwitchSettings,
+    tunnel_dev: &str,
+    server_ips: &[IpAddr],
+    allow_ports: &[u16],
+    ipv6: bool,
+) -> Vec<Vec<String>> {
+    let rule = |parts: &[&str]| parts.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+    let destinations: Vec<String> = server_ips
+        .iter()
+        .filter(|ip| ip.is_ipv6() == ipv6)
+        .map(|ip| ip.to_string())
+        .chain(
+            settings
+                .allow_destinations
+                .iter()
+                .filter(|c| is_v6_cidr(c) == ipv6)
+                .cloned(),
+        )
+        .collect();
+
+    let mut rules = Vec::new();
+    for lan in &settings.lan_interfaces {
+        rules.push(rule(&["-i", lan, "-o", tunnel_dev, "-j", "RETURN"]));
+        for other in &settings.lan_interfaces {
+            rules.push(rule(&["-i", lan, "-o", other, "-j", "RETURN"]));
+        }
+        for dest in &destinations {
+            rules.push(rule(&["-i", lan, "-d", dest, "-j", "RETURN"]));
+        }
+        for port in allow_ports {
+            let port = port.to_string();
+            for proto in ["tcp", "udp"] {
+                rules.push(rule(&[
+                    "-i", lan, "-p", proto, "--dport", &port, "-j", "RETURN",
+                ]));
+            }
+        }
+        rules.push(rule(&["-i", lan, "-j", "REJECT"]));
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_rules_per_family() {
+        let settings = KillSwitchSettings {
+            enabled: true,
+            lan_interfaces: vec!["br0".into()],
+            allow_destinations: vec!["192.168.50.0/24".into(), "2001:db8::/32".into()],
+        };
+        let ips: Vec<IpAddr> = vec!["203.0.113.10".parse().unwrap()];
+        let v4: Vec<String> = build_rules(&settings, "opkgtun2", &ips, &[53], false)
+            .into_iter()
+            .map(|r| r.join(" "))
+            .collect();
+        assert_eq!(
+            v4,
+            vec![
+                "-i br0 -o opkgtun2 -j RETURN",
+                "-i br0 -o br0 -j RETURN",
+                "-i br0 -d 203.0.113.10 -j RETURN",
+                "-i br0 -d 192.168.50.0/24 -j RETURN",
+                "-i br0 -p tcp --dport 53 -j RETURN",
+                "-i br0 -p udp --dport 53 -j RETURN",
+                "-i br0 -j REJECT",
+            ]
+        );
+        let v6 = build_rules(&settings, "opkgtun2", &ips, &[], true);
+        assert!(v6
+            .iter()
+            .any(|r| r.join(" ") == "-i br0 -d 2001:db8::/32 -j RETURN"));
+        assert!(!v6.iter().any(|r| r.join(" ").contains("203.0.113.10")));
+    }
+}
@@ -4,7 +4,6 @@ use std::fs::{self, File, OpenOptions};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn init(settings: &LogSettings) -> Result<(), String> {
     let level = parse_level(&settings.level);
@@ -47,10 +46,7 @@ struct TTLogger {
 
 impl TTLogger {
     fn format_line(record: &Record<'_>) -> String {
-        let ts = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
+        let ts = crate::util::unix_now();
         format!("[{}] {} {}", ts, record.level(), record.args())
     }
 }
@@ -78,7 +74,8 @@ impl Log for TTLogger {
     fn flush(&self) {}
 }
 
-struct RotatingFile {
+/// Append-only line file rotated to `<path>.1` … `<path>.<keep>` by size.
+pub struct RotatingFile {
     path: PathBuf,
     rotate_size: u64,
     rotate_keep: usize,
@@ -86,7 +83,7 @@ struct RotatingFile {
 }
 
 impl RotatingFile {
-    fn new(path: PathBuf, rotate_size: u64, rotate_keep: usize) -> Result<Self, String> {
+    pub fn new(path: PathBuf, rotate_size: u64, rotate_keep: usize) -> Result<Self, String> {
         let parent = path
             .parent()
             .ok_or_else(|| format!("invalid log file path: {}", path.display()))?;
@@ -101,7 +98,7 @@ impl RotatingFile {
         })
     }
 
-    fn write_line(&mut self, line: &str) -> Result<(), String> {
+    pub fn write_line(&mut self, line: &str) -> Result<(), String> {
         let line_bytes = line.as_bytes();
         let required = line_bytes.len() as u64 + 1;
 
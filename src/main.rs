@@ -1,9 +1,29 @@
 mod auth;
+mod boot;
 mod config;
+mod daemon;
+mod dns;
+mod events;
+mod journal;
+mod killswitch;
 mod logger;
 mod logs;
+mod ndm;
+mod pidfile;
+mod probes;
+mod process;
+mod quality;
+mod quota;
+mod rci;
+mod registry;
+mod routelists;
 mod routing;
+mod scheduler;
+mod sessions;
+mod state;
+mod traffic;
 mod tunnel;
+mod util;
 mod webui;
 
 use clap::Parser;
@@ -31,6 +51,10 @@ struct Args {
     /// Test configuration and exit
     #[arg(short, long)]
     test: bool,
+
+    /// PID file to lock and write (overrides `startup.pidfile`)
+    #[arg(long)]
+    pidfile: Option<String>,
 }
 
 fn main() {
@@ -44,16 +68,19 @@ fn main() {
         }
     };
 
-    if let Err(e) = logger::init(&cfg.logging) {
-        eprintln!("Failed to initialize logger: {}", e);
-        std::process::exit(1);
-    }
-    logs::init_global_buffer(cfg.logging.max_lines);
+    let umask = match daemon::parse_umask(&cfg.startup.umask) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Failed to load config: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    log::info!(
-        "trusttunnel-keenetic v{} starting",
-        env!("TRUSTTUNNEL_VERSION")
-    );
+    // Before any thread exists: the environment is not thread-safe, and the
+    // scheduler's `localtime_r` reads `TZ` from here.
+    if !cfg.schedule.timezone.trim().is_empty() {
+        std::env::set_var("TZ", cfg.schedule.timezone.trim());
+    }
 
     if args.test {
         println!("Configuration OK");
@@ -62,112 +89,211 @@ fn main() {
         println!("Protocol: {}", cfg.tunnel.upstream_protocol);
         println!("VPN mode: {}", cfg.tunnel.vpn_mode);
         println!("WebUI port: {}", cfg.webui.port);
+        if !cfg.tunnels.is_empty() {
+            let ids: Vec<&str> = cfg.tunnels.iter().map(|t| t.id.as_str()).collect();
+            println!("Extra tunnels: {}", ids.join(", "));
+        }
         std::process::exit(0);
     }
 
-    // Daemonize if requested
-    if args.daemon && !args.foreground {
-        daemonize();
+    // Daemonize if requested. Logging starts after the fork, and the
+    // waiting parent exits only once startup has succeeded or failed.
+    let mut readiness = if args.daemon && !args.foreground {
+        daemon::daemonize(umask)
+    } else {
+        daemon::Readiness::foreground()
+    };
+
+    // Before the first thread, the WebUI's included, so none of them can
+    // take a SIGTERM/SIGINT and kill the process without a teardown.
+    block_termination_signals();
+
+    if let Err(e) = logger::init(&cfg.logging) {
+        eprintln!("Failed to initialize logger: {}", e);
+        readiness.fail(&format!("cannot initialize logger: {}", e));
+        std::process::exit(1);
     }
+    logs::init_global_buffer(cfg.logging.max_lines);
 
-    // Shared config
-    let config = Arc::new(Mutex::new(cfg.clone()));
+    log::info!(
+        "trusttunnel-keenetic v{} starting",
+        env!("TRUSTTUNNEL_VERSION")
+    );
 
-    // Create tunnel manager
-    let tunnel = tunnel::TunnelManager::new(cfg.tunnel.clone(), &cfg.routing);
+    // Held until exit; taken before anything touches routing.
+    let pidfile = args.pidfile.as_deref().unwrap_or(&cfg.startup.pidfile);
+    let _instance_lock = match pidfile::InstanceLock::acquire(pidfile) {
+        Ok(lock) => lock,
+        Err(e) => exit_startup(&mut readiness, e),
+    };
 
-    // Set up signal handlers
-    let tunnel_for_signal = tunnel.clone();
-    ctrlc_handler(tunnel_for_signal);
+    // Bind early so a taken port fails startup before any tunnel connects.
+    let server = match webui::WebUI::bind(&cfg.webui.bind, cfg.webui.port) {
+        Ok(s) => s,
+        Err(e) => exit_startup(&mut readiness, e),
+    };
+
+    ndm::init(&cfg.routing);
 
-    // Start tunnel monitor in background thread
-    let tunnel_monitor = tunnel.clone();
+    // Shared config
+    let config = Arc::new(Mutex::new(cfg.clone()));
+
+    // Route list sources are refreshed in the background; cached copies are
+    // loaded synchronously so auto-connect already sees them.
+    let route_lists = routelists::RouteLists::new(&cfg.route_lists);
+    let route_lists_refresh = route_lists.clone();
     std::thread::Builder::new()
-        .name("tunnel-monitor".into())
+        .name("route-lists".into())
         .spawn(move || {
-            tunnel_monitor.monitor_loop();
+            route_lists_refresh.refresh_loop();
         })
-        .expect("failed to spawn tunnel monitor thread");
+        .expect("failed to spawn route list thread");
 
-    // Auto-connect if endpoint is configured
-    if !cfg.tunnel.hostname.is_empty() && !cfg.tunnel.addresses.is_empty() {
-        if let Err(e) = tunnel.start() {
-            log::warn!("Auto-connect failed: {}", e);
-        }
+    // Create one tunnel manager per configured tunnel
+    let tunnels = match registry::TunnelRegistry::new(&cfg, route_lists.clone()) {
+        Ok(r) => Arc::new(r),
+        Err(e) => exit_startup(
+            &mut readiness,
+            format!("Invalid tunnel configuration: {}", e),
+        ),
+    };
+
+    // Set up signal handlers
+    ctrlc_handler(tunnels.clone());
+
+    for (id, tunnel) in tunnels.iter() {
+        // Start tunnel monitor in background thread
+        let tunnel_monitor = tunnel.clone();
+        std::thread::Builder::new()
+            .name(format!("tunnel-monitor-{}", id))
+            .spawn(move || {
+                tunnel_monitor.monitor_loop();
+            })
+            .expect("failed to spawn tunnel monitor thread");
+        let tunnel_traffic = tunnel.clone();
+        std::thread::Builder::new()
+            .name(format!("tunnel-traffic-{}", id))
+            .spawn(move || tunnel_traffic.traffic_loop())
+            .expect("failed to spawn tunnel traffic thread");
+        let tunnel_quality = tunnel.clone();
+        std::thread::Builder::new()
+            .name(format!("tunnel-quality-{}", id))
+            .spawn(move || tunnel_quality.quality_loop())
+            .expect("failed to spawn tunnel quality thread");
     }
 
-    // Start WebUI (blocks on the main thread)
-    let ndm_host = if cfg.webui.ndm_host.is_empty() {
-        auth::detect_ndm_host()
-    } else {
-        cfg.webui.ndm_host.clone()
+    let scheduler = match scheduler::Scheduler::new(&cfg.schedule, tunnels.clone()) {
+        Ok(s) => s,
+        Err(e) => exit_startup(&mut readiness, format!("Invalid schedule: {}", e)),
     };
+
+    // Auto-connect in the background so the WebUI is up while the boot gate
+    // waits for WAN, NDM and the clock.
+    let startup_cfg = cfg.clone();
+    let startup_tunnels = tunnels.clone();
+    let startup_scheduler = scheduler.clone();
+    std::thread::Builder::new()
+        .name("autostart".into())
+        .spawn(move || autostart(&startup_cfg, &startup_tunnels, startup_scheduler))
+        .expect("failed to spawn autostart thread");
+
+    // Start WebUI (blocks on the main thread)
+    let ndm_host = auth::resolve_ndm_host(&cfg.webui.ndm_host);
     log::info!("NDM API endpoint: {}:{}", ndm_host, cfg.webui.ndm_port);
 
-    let web = webui::WebUI::new(tunnel, config, args.config, ndm_host, cfg.webui.ndm_port);
-    web.run(&cfg.webui.bind, cfg.webui.port);
+    let web = webui::WebUI::new(
+        tunnels,
+        route_lists,
+        scheduler,
+        config,
+        args.config,
+        ndm_host,
+        cfg.webui.ndm_port,
+    );
+    readiness.ready();
+    web.run(server);
 }
 
-fn daemonize() {
-    #[cfg(unix)]
-    {
-        use nix::unistd::{fork, setsid, ForkResult};
-
-        match unsafe { fork() } {
-            Ok(ForkResult::Parent { .. }) => {
-                std::process::exit(0);
-            }
-            Ok(ForkResult::Child) => {
-                let _ = setsid();
-                let _ = nix::unistd::chdir("/");
-                // Redirect stdio to /dev/null
-                let devnull = std::fs::OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open("/dev/null");
-                if let Ok(f) = devnull {
-                    use std::os::unix::io::AsRawFd;
-                    let fd = f.as_raw_fd();
-                    let _ = nix::unistd::dup2(fd, 0);
-                    let _ = nix::unistd::dup2(fd, 1);
-                    let _ = nix::unistd::dup2(fd, 2);
-                }
-            }
-            Err(e) => {
-                log::error!("Fork failed: {}", e);
-                std::process::exit(1);
-            }
+/// Log a startup failure, hand it to a waiting parent, and exit.
+fn exit_startup(readiness: &mut daemon::Readiness, msg: String) -> ! {
+    log::error!("Cannot start: {}", msg);
+    readiness.fail(&msg);
+    std::process::exit(1);
+}
+
+/// Auto-connect every tunnel whose endpoint is configured, as the autostart
+/// policy allows, then hand the tunnels it drives to the scheduler.
+fn autostart(
+    cfg: &WrapperConfig,
+    tunnels: &registry::TunnelRegistry,
+    scheduler: Option<Arc<scheduler::Scheduler>>,
+) {
+    if cfg.startup.boot_wait {
+        boot::wait(std::time::Duration::from_secs(cfg.startup.boot_timeout));
+    }
+    // Clean up after a previous run that was killed before it could tear
+    // down. Undoing its NDM changes needs NDM, hence after the boot gate.
+    for (_, tunnel) in tunnels.iter() {
+        tunnel.recover_previous_run(cfg.startup.orphan_client);
+    }
+
+    let endpoints = std::iter::once((registry::MAIN_TUNNEL_ID, &cfg.tunnel))
+        .chain(cfg.tunnels.iter().map(|t| (t.id.trim(), &t.tunnel)));
+    for (id, settings) in endpoints {
+        if settings.hostname.is_empty() || settings.addresses.is_empty() {
+            continue;
+        }
+        if scheduler.as_ref().is_some_and(|s| s.drives(id)) {
+            continue;
+        }
+        if let Err(e) = tunnels.autostart(id, cfg.startup.autostart) {
+            log::warn!("Auto-connect of '{}' failed: {}", id, e);
         }
     }
 
-    #[cfg(not(unix))]
-    {
-        log::warn!("--daemon is only supported on Unix-like systems");
+    if let Some(scheduler) = scheduler {
+        std::thread::Builder::new()
+            .name("scheduler".into())
+            .spawn(move || scheduler.run_loop())
+            .expect("failed to spawn scheduler thread");
     }
 }
 
-fn ctrlc_handler(tunnel: Arc<tunnel::TunnelManager>) {
+#[cfg(unix)]
+fn termination_signals() -> nix::sys::signal::SigSet {
+    use nix::sys::signal::{SigSet, Signal};
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGTERM);
+    mask.add(Signal::SIGINT);
+    mask
+}
+
+/// Block SIGTERM and SIGINT in the main thread. Threads spawned afterwards
+/// inherit the mask, so only the waiter started by `ctrlc_handler` receives
+/// them.
+fn block_termination_signals() {
     #[cfg(unix)]
     {
-        use nix::sys::signal::{pthread_sigmask, SigSet, SigmaskHow, Signal};
-
-        // Block signals in the main thread before worker threads are started.
-        // New threads inherit the mask; a dedicated waiter thread handles shutdown.
-        let mut mask = SigSet::empty();
-        mask.add(Signal::SIGTERM);
-        mask.add(Signal::SIGINT);
+        use nix::sys::signal::{pthread_sigmask, SigmaskHow};
+        let mask = termination_signals();
         if let Err(e) = pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&mask), None) {
             log::error!("Failed to install signal mask: {}", e);
-            return;
         }
+    }
+}
 
+fn ctrlc_handler(tunnels: Arc<registry::TunnelRegistry>) {
+    #[cfg(unix)]
+    {
+        // A dedicated waiter thread handles shutdown.
+        let mask = termination_signals();
         let _ = std::thread::Builder::new()
             .name("signal-handler".into())
             .spawn(move || loop {
                 match mask.wait() {
                     Ok(sig) => {
                         log::info!("Received signal {:?}, shutting down...", sig);
-                        tunnel.stop();
+                        tunnels.stop_all();
                         std::process::exit(0);
                     }
                     Err(_) => continue,
@@ -177,6 +303,6 @@ fn ctrlc_handler(tunnel: Arc<tunnel::TunnelManager>) {
 
     #[cfg(not(unix))]
     {
-        let _ = tunnel;
+        let _ = tunnels;
     }
 }
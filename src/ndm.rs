@@ -0,0 +1,848 @@
+use crate::config::RoutingSettings;
+use crate::rci::{self, InterfaceInfo, RciClient, RciError, RciErrorKind, RouteEntry};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const NDM_RETRY_BASE_DELAY_MS: u64 = 200;
+const NDM_MAX_ATTEMPTS: u32 = 10;
+/// NDM error codes seen while the core is still starting or the interface
+/// object is being created.
+const TRANSIENT_CODES: &[u32] = &[0xcffd0060, 0xcffd009f];
+/// Messages of the same conditions; RCI reports them with decimal codes
+/// that vary between firmware releases.
+const TRANSIENT_MESSAGES: &[&str] = &[
+    "failed to initialize",
+    "temporarily unavailable",
+    "unable to find",
+];
+
+/// A typed NDM configuration command. Rendered as a CLI string for `ndmc`
+/// or as a JSON request for RCI.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum NdmCommand {
+    Interface {
+        name: String,
+    },
+    NoInterface {
+        name: String,
+    },
+    InterfaceUp {
+        name: String,
+    },
+    InterfaceDown {
+        name: String,
+    },
+    InterfaceAddress {
+        name: String,
+        address: String,
+        mask: String,
+    },
+    InterfaceMtu {
+        name: String,
+        mtu: u16,
+    },
+    InterfaceGlobalAuto {
+        name: String,
+    },
+    InterfaceAdjustMss {
+        name: String,
+    },
+    InterfaceSecurityPublic {
+        name: String,
+    },
+    InterfaceDescription {
+        name: String,
+        description: String,
+    },
+    DefaultRoute {
+        name: String,
+        ipv6: bool,
+    },
+    NoDefaultRoute {
+        name: String,
+        ipv6: bool,
+    },
+    /// Static route for a CIDR `prefix` (IPv4 or IPv6) via the interface.
+    Route {
+        name: String,
+        prefix: String,
+    },
+    NoRoute {
+        name: String,
+        prefix: String,
+    },
+    /// Static DNS server for the router's DNS proxy, optionally limited to
+    /// `domain` and bound to an interface.
+    NameServer(NameServer),
+    NoNameServer(NameServer),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NameServer {
+    pub address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+}
+
+impl std::fmt::Display for NameServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ip name-server {}", self.address)?;
+        if let Some(domain) = &self.domain {
+            write!(f, " {}", domain)?;
+        }
+        if let Some(interface) = &self.interface {
+            write!(f, " on {}", interface)?;
+        }
+        Ok(())
+    }
+}
+
+impl NameServer {
+    /// Parse an `ip name-server <addr>[ <domain>][ on <iface>]` line of
+    /// the running-config.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line
+            .trim()
+            .strip_prefix("ip name-server ")?
+            .split_whitespace();
+        let mut server = Self {
+            address: words.next()?.to_string(),
+            domain: None,
+            interface: None,
+        };
+        while let Some(word) = words.next() {
+            if word == "on" {
+                server.interface = Some(words.next()?.to_string());
+            } else {
+                server.domain = Some(word.to_string());
+            }
+        }
+        Some(server)
+    }
+
+    fn rci_request(&self, no: Option<bool>) -> rci::Request {
+        rci::Request::Ip(rci::IpRequest {
+            name_server: Some(rci::NameServerRequest {
+                address: self.address.clone(),
+                domain: self.domain.clone(),
+                interface: self.interface.clone(),
+                no,
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+impl std::fmt::Display for NdmCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NdmCommand::Interface { name } => write!(f, "interface {}", name),
+            NdmCommand::NoInterface { name } => write!(f, "no interface {}", name),
+            NdmCommand::InterfaceUp { name } => write!(f, "interface {} up", name),
+            NdmCommand::InterfaceDown { name } => write!(f, "interface {} down", name),
+            NdmCommand::InterfaceAddress {
+                name,
+                address,
+                mask,
+            } => write!(f, "interface {} ip address {} {}", name, address, mask),
+            NdmCommand::InterfaceMtu { name, mtu } => {
+                write!(f, "interface {} ip mtu {}", name, mtu)
+            }
+            NdmCommand::InterfaceGlobalAuto { name } => {
+                write!(f, "interface {} ip global auto", name)
+            }
+            NdmCommand::InterfaceAdjustMss { name } => {
+                write!(f, "interface {} ip tcp adjust-mss pmtu", name)
+            }
+            NdmCommand::InterfaceDescription { name, description } => {
+                write!(f, "interface {} description {}", name, description)
+            }
+            NdmCommand::InterfaceSecurityPublic { name } => {
+                write!(f, "interface {} security-level public", name)
+            }
+            NdmCommand::DefaultRoute { name, ipv6 } => {
+                write!(f, "{} route default {}", ip_family(*ipv6), name)
+            }
+            NdmCommand::NoDefaultRoute { name, ipv6 } => {
+                write!(f, "no {} route default {}", ip_family(*ipv6), name)
+            }
+            NdmCommand::Route { name, prefix } => write!(f, "{}", prefix_route(name, prefix)),
+            NdmCommand::NoRoute { name, prefix } => {
+                write!(f, "no {}", prefix_route(name, prefix))
+            }
+            NdmCommand::NameServer(server) => write!(f, "{}", server),
+            NdmCommand::NoNameServer(server) => write!(f, "no {}", server),
+        }
+    }
+}
+
+/// `ip route <network> <mask> <name> auto` or `ipv6 route <prefix> <name>`.
+fn prefix_route(name: &str, prefix: &str) -> String {
+    match split_ipv4_prefix(prefix) {
+        Some((network, mask)) => format!("ip route {} {} {} auto", network, mask, name),
+        None => format!("ipv6 route {} {}", prefix, name),
+    }
+}
+
+/// Network address and dotted netmask of an IPv4 CIDR; `None` for IPv6.
+fn split_ipv4_prefix(prefix: &str) -> Option<(String, String)> {
+    let (addr, len) = prefix.split_once('/').unwrap_or((prefix, "32"));
+    let addr: std::net::Ipv4Addr = addr.parse().ok()?;
+    let len: u32 = len.parse().ok().filter(|l| *l <= 32)?;
+    let mask = if len == 0 { 0 } else { u32::MAX << (32 - len) };
+    let network = std::net::Ipv4Addr::from(u32::from(addr) & mask);
+    Some((
+        network.to_string(),
+        std::net::Ipv4Addr::from(mask).to_string(),
+    ))
+}
+
+fn ip_family(ipv6: bool) -> &'static str {
+    if ipv6 {
+        "ipv6"
+    } else {
+        "ip"
+    }
+}
+
+impl NdmCommand {
+    /// Parse the CLI commands journaled as plain strings before commands
+    /// were typed: `[no] interface <name>`, `interface <name> up|down` and
+    /// `[no] ip|ipv6 route default <name>`.
+    pub fn from_cli(line: &str) -> Option<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let name = |w: &str| w.to_string();
+        match words.as_slice() {
+            ["interface", n] => Some(NdmCommand::Interface { name: name(n) }),
+            ["no", "interface", n] => Some(NdmCommand::NoInterface { name: name(n) }),
+            ["interface", n, "up"] => Some(NdmCommand::InterfaceUp { name: name(n) }),
+            ["interface", n, "down"] => Some(NdmCommand::InterfaceDown { name: name(n) }),
+            [family @ ("ip" | "ipv6"), "route", "default", n] => Some(NdmCommand::DefaultRoute {
+                name: name(n),
+                ipv6: *family == "ipv6",
+            }),
+            ["no", family @ ("ip" | "ipv6"), "route", "default", n] => {
+                Some(NdmCommand::NoDefaultRoute {
+                    name: name(n),
+                    ipv6: *family == "ipv6",
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn rci_request(&self) -> rci::Request {
+        let iface = |name: &str| rci::InterfaceRequest {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let iface_ip = |name: &str, ip: rci::InterfaceIp| rci::InterfaceRequest {
+            ip: Some(ip),
+            ..iface(name)
+        };
+        let route = |name: &str, ipv6: bool, no: Option<bool>| {
+            let req = rci::IpRequest {
+                route: Some(rci::RouteRequest {
+                    default: true,
+                    interface: name.to_string(),
+                    no,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            if ipv6 {
+                rci::Request::Ipv6(req)
+            } else {
+                rci::Request::Ip(req)
+            }
+        };
+
+        match self {
+            NdmCommand::Interface { name } => rci::Request::Interface(iface(name)),
+            NdmCommand::NoInterface { name } => rci::Request::Interface(rci::InterfaceRequest {
+                no: Some(true),
+                ..iface(name)
+            }),
+            NdmCommand::InterfaceUp { name } => rci::Request::Interface(rci::InterfaceRequest {
+                up: Some(true),
+                ..iface(name)
+            }),
+            NdmCommand::InterfaceDown { name } => rci::Request::Interface(rci::InterfaceRequest {
+                down: Some(true),
+                ..iface(name)
+            }),
+            NdmCommand::InterfaceAddress {
+                name,
+                address,
+                mask,
+            } => rci::Request::Interface(iface_ip(
+                name,
+                rci::InterfaceIp {
+                    address: Some(rci::IpAddress {
+                        address: address.clone(),
+                        mask: mask.clone(),
+                    }),
+                    ..Default::default()
+                },
+            )),
+            NdmCommand::InterfaceMtu { name, mtu } => rci::Request::Interface(iface_ip(
+                name,
+                rci::InterfaceIp {
+                    mtu: Some(*mtu),
+                    ..Default::default()
+                },
+            )),
+            NdmCommand::InterfaceGlobalAuto { name } => rci::Request::Interface(iface_ip(
+                name,
+                rci::InterfaceIp {
+                    global: Some(rci::IpGlobal { auto: true }),
+                    ..Default::default()
+                },
+            )),
+            NdmCommand::InterfaceAdjustMss { name } => rci::Request::Interface(iface_ip(
+                name,
+                rci::InterfaceIp {
+                    tcp: Some(rci::IpTcp {
+                        adjust_mss: rci::AdjustMss { pmtu: true },
+                    }),
+                    ..Default::default()
+                },
+            )),
+            NdmCommand::InterfaceDescription { name, description } => {
+                rci::Request::Interface(rci::InterfaceRequest {
+                    description: Some(description.clone()),
+                    ..iface(name)
+                })
+            }
+            NdmCommand::InterfaceSecurityPublic { name } => {
+                rci::Request::Interface(rci::InterfaceRequest {
+                    security_level: Some(rci::SecurityLevel { public: true }),
+                    ..iface(name)
+                })
+            }
+            NdmCommand::DefaultRoute { name, ipv6 } => route(name, *ipv6, None),
+            NdmCommand::NoDefaultRoute { name, ipv6 } => route(name, *ipv6, Some(true)),
+            NdmCommand::Route { name, prefix } => prefix_route_request(name, prefix, None),
+            NdmCommand::NoRoute { name, prefix } => prefix_route_request(name, prefix, Some(true)),
+            NdmCommand::NameServer(server) => server.rci_request(None),
+            NdmCommand::NoNameServer(server) => server.rci_request(Some(true)),
+        }
+    }
+}
+
+fn prefix_route_request(name: &str, prefix: &str, no: Option<bool>) -> rci::Request {
+    match split_ipv4_prefix(prefix) {
+        Some((network, mask)) => rci::Request::Ip(rci::IpRequest {
+            route: Some(rci::RouteRequest {
+                network: Some(network),
+                mask: Some(mask),
+                interface: name.to_string(),
+                auto: Some(true),
+                no,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        None => rci::Request::Ipv6(rci::IpRequest {
+            route: Some(rci::RouteRequest {
+                prefix: Some(prefix.to_string()),
+                interface: name.to_string(),
+                no,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+    }
+}
+
+/// Failed NDM command, classified once where it was produced.
+#[derive(Debug, Clone)]
+pub struct NdmError {
+    pub via: &'static str,
+    pub code: String,
+    pub message: String,
+    pub transient: bool,
+}
+
+impl std::fmt::Display for NdmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.code.is_empty() {
+            write!(f, "{}: {}", self.via, self.message)
+        } else {
+            write!(f, "{}: [{}] {}", self.via, self.code, self.message)
+        }
+    }
+}
+
+impl From<RciError> for NdmError {
+    fn from(e: RciError) -> Self {
+        let transient = match e.kind {
+            RciErrorKind::Transport => true,
+            RciErrorKind::Rejected => is_transient(&e.code, &e.message),
+        };
+        Self {
+            via: "rci",
+            code: e.code.clone(),
+            message: e.to_string(),
+            transient,
+        }
+    }
+}
+
+impl NdmError {
+    /// Classify free-text `ndmc` output. ndmc has no structured status, so
+    /// the code is taken from the first `0x...` token.
+    fn from_ndmc(cmd: &str, exit: i32, output: &str) -> Self {
+        let code = output
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .find(|t| t.len() > 2 && t.to_ascii_lowercase().starts_with("0x"))
+            .map(|t| t.to_ascii_lowercase())
+            .unwrap_or_default();
+        let transient = is_transient(&code, output);
+        Self {
+            via: "ndmc",
+            code,
+            message: format!("'{}' exit={} {}", cmd, exit, output),
+            transient,
+        }
+    }
+}
+
+/// Whether an NDM error is worth retrying. `code` is hex (`0x...`, from
+/// ndmc) or decimal (from RCI).
+fn is_transient(code: &str, message: &str) -> bool {
+    let code = code.trim().to_ascii_lowercase();
+    let numeric = match code.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => code.parse::<u32>().ok(),
+    };
+    let message = message.to_ascii_lowercase();
+    numeric.is_some_and(|c| TRANSIENT_CODES.contains(&c))
+        || TRANSIENT_MESSAGES.iter().any(|m| message.contains(m))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    /// RCI first, `ndmc` when RCI is unreachable.
+    Auto,
+    Rci,
+    Ndmc,
+}
+
+struct NdmConfig {
+    backend: Backend,
+    rci: RciClient,
+}
+
+static CONFIG: OnceLock<NdmConfig> = OnceLock::new();
+
+/// Select the NDM backend from config. Call once at startup.
+pub fn init(settings: &RoutingSettings) {
+    let backend = match settings.ndm_api.to_ascii_lowercase().as_str() {
+        "rci" => Backend::Rci,
+        "ndmc" => Backend::Ndmc,
+        _ => Backend::Auto,
+    };
+    let host = crate::auth::resolve_ndm_host(&settings.rci_host);
+    let _ = CONFIG.set(NdmConfig {
+        backend,
+        rci: RciClient::new(&host, settings.rci_port),
+    });
+}
+
+fn config() -> &'static NdmConfig {
+    CONFIG.get_or_init(|| NdmConfig {
+        backend: Backend::Auto,
+        rci: RciClient::new("127.0.0.1", 79),
+    })
+}
+
+/// Human-readable description of the active backend for logs.
+pub fn backend_name() -> String {
+    match config().backend {
+        Backend::Auto => format!("rci, fallback ndmc={}", find_ndmc()),
+        Backend::Rci => "rci".into(),
+        Backend::Ndmc => format!("ndmc={}", find_ndmc()),
+    }
+}
+
+fn find_ndmc() -> &'static str {
+    static PATHS: &[&str] = &["/usr/bin/ndmc", "/bin/ndmc", "/sbin/ndmc"];
+    for p in PATHS {
+        if std::path::Path::new(p).exists() {
+            return p;
+        }
+    }
+    "ndmc"
+}
+
+fn ndm_lock() -> &'static Mutex<()> {
+    static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(|| Mutex::new(()))
+}
+
+fn ndmc_exec_once(cmd: &str) -> Result<String, NdmError> {
+    let bin = find_ndmc();
+    let output = Command::new(bin)
+        .args(["-c", cmd])
+        .output()
+        .map_err(|e| NdmError {
+            via: "ndmc",
+            code: String::new(),
+            message: format!("exec '{}' error: {}", cmd, e),
+            transient: false,
+        })?;
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    let combined = match (stdout.is_empty(), stderr.is_empty()) {
+        (false, false) => format!("{} | {}", stdout, stderr),
+        (false, true) => stdout,
+        (true, false) => stderr,
+        (true, true) => String::new(),
+    };
+    if output.status.success() {
+        Ok(combined)
+    } else {
+        Err(NdmError::from_ndmc(
+            cmd,
+            output.status.code().unwrap_or(-1),
+            &combined,
+        ))
+    }
+}
+
+fn exec_once(cmd: &NdmCommand) -> Result<(String, &'static str), NdmError> {
+    let cfg = config();
+    match cfg.backend {
+        Backend::Ndmc => ndmc_exec_once(&cmd.to_string()).map(|out| (out, "ndmc")),
+        Backend::Rci => cfg
+            .rci
+            .execute(&cmd.rci_request())
+            .map(|v| (v.to_string(), "rci"))
+            .map_err(NdmError::from),
+        Backend::Auto => match cfg.rci.execute(&cmd.rci_request()) {
+            Ok(v) => Ok((v.to_string(), "rci")),
+            Err(e) if e.kind == RciErrorKind::Transport => {
+                log::debug!("[ndm] {}, falling back to ndmc", e);
+                ndmc_exec_once(&cmd.to_string()).map(|out| (out, "ndmc"))
+            }
+            Err(e) => Err(e.into()),
+        },
+    }
+}
+
+fn summarize_output(output: &str) -> String {
+    let trimmed = output.trim();
+    const MAX_LEN: usize = 240;
+    if trimmed.len() > MAX_LEN {
+        let mut end = MAX_LEN;
+        while !trimmed.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}...", &trimmed[..end])
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Execute a configuration command, retrying transient failures.
+pub fn run(cmd: &NdmCommand) -> Result<String, NdmError> {
+    let _guard = ndm_lock().lock().unwrap();
+    let mut attempt = 1;
+    loop {
+        match exec_once(cmd) {
+            Ok((output, via)) => {
+                let summary = summarize_output(&output);
+                let msg = if summary.is_empty() {
+                    format!("[ndm] ok via {}: {}", via, cmd)
+                } else {
+                    format!("[ndm] ok via {}: {} ({})", via, cmd, summary)
+                };
+                log::info!("{}", msg);
+                crate::logs::global_buffer().push(msg);
+                return Ok(output);
+            }
+            Err(err) => {
+                let msg = format!(
+                    "[ndm] attempt {}/{} failed: {}",
+                    attempt, NDM_MAX_ATTEMPTS, err
+                );
+                log::warn!("{}", msg);
+                crate::logs::global_buffer().push(msg);
+                if err.transient && attempt < NDM_MAX_ATTEMPTS {
+                    let backoff = (NDM_RETRY_BASE_DELAY_MS * attempt as u64).min(1000);
+                    std::thread::sleep(Duration::from_millis(backoff));
+                    attempt += 1;
+                    continue;
+                }
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// Run a read-only `show` query through the configured backend. `ndmc`
+/// output is turned into the same typed value by `parse`.
+fn query<T>(
+    rci_call: impl Fn(&RciClient) -> Result<T, RciError>,
+    cli: &str,
+    parse: impl Fn(&str) -> T,
+) -> Result<T, NdmError> {
+    let _guard = ndm_lock().lock().unwrap();
+    let cfg = config();
+    let via_ndmc = || ndmc_exec_once(cli).map(|out| parse(&out));
+    match cfg.backend {
+        Backend::Ndmc => via_ndmc(),
+        Backend::Rci => Ok(rci_call(&cfg.rci)?),
+        Backend::Auto => match rci_call(&cfg.rci) {
+            Ok(v) => Ok(v),
+            Err(e) if e.kind == RciErrorKind::Transport => via_ndmc(),
+            Err(e) => Err(e.into()),
+        },
+    }
+}
+
+/// Current NDM running-config, one line per entry.
+pub fn running_config() -> Result<Vec<String>, NdmError> {
+    query(
+        |rci| rci.running_config().map(|rc| rc.message),
+        "show running-config",
+        |out| out.lines().map(|l| l.to_string()).collect(),
+    )
+}
+
+/// NDM view of one interface (`show interface <name>`).
+pub fn show_interface(name: &str) -> Result<InterfaceInfo, NdmError> {
+    query(
+        |rci| rci.show_interface(name),
+        &format!("show interface {}", name),
+        parse_interface,
+    )
+}
+
+/// NDM routing table (`show ip route` / `show ipv6 route`).
+pub fn show_routes(ipv6: bool) -> Result<Vec<RouteEntry>, NdmError> {
+    query(
+        |rci| rci.show_routes(ipv6),
+        &format!("show {} route", ip_family(ipv6)),
+        parse_routes,
+    )
+}
+
+/// DNS servers in use by the router's DNS proxy (`show ip name-server`).
+pub fn show_name_servers() -> Result<Vec<String>, NdmError> {
+    query(
+        |rci| rci.show_name_servers(),
+        "show ip name-server",
+        |out| {
+            key_values(out)
+                .filter(|(key, _)| key == "address")
+                .map(|(_, value)| value.to_string())
+                .collect()
+        },
+    )
+}
+
+/// Whether NDM answers queries yet; it does not for a while after boot.
+pub fn ready() -> Result<(), NdmError> {
+    show_name_servers().map(|_| ())
+}
+
+/// `key: value` pairs of ndmc text output, keys lowercased.
+fn key_values(text: &str) -> impl Iterator<Item = (String, &str)> {
+    text.lines().filter_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        let key = key.trim();
+        if key.is_empty() || key.contains(' ') {
+            return None;
+        }
+        Some((key.to_ascii_lowercase(), value.trim()))
+    })
+}
+
+fn parse_interface(text: &str) -> InterfaceInfo {
+    let mut info = InterfaceInfo::default();
+    for (key, value) in key_values(text) {
+        let value = value.to_string();
+        match key.as_str() {
+            "id" => info.id = value,
+            "description" => info.description = value,
+            "link" => info.link = value,
+            "connected" => info.connected = value,
+            "state" => info.state = value,
+            "address" => info.address = value,
+            "mask" => info.mask = value,
+            "mtu" => info.mtu = value.parse().ok(),
+            "uptime" => info.uptime = value.parse().ok(),
+            "security-level" => info.security_level = value,
+            _ => {}
+        }
+    }
+    info
+}
+
+/// Routes from `show ip route`. The CLI prints a table:
+///
+/// ```text
+/// ================================================================================
+///          Destination         Gateway        Interface  Metric
+/// ================================================================================
+///            0.0.0.0/0        10.0.0.1 GigabitEthernet1       0
+/// ```
+///
+/// Some firmware prints `destination:` blocks instead; both are accepted.
+/// Anything else yields no routes, which callers treat as unknown.
+fn parse_routes(text: &str) -> Vec<RouteEntry> {
+    let table: Vec<RouteEntry> = text
+        .lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [destination, gateway, interface, metric]
+                    if destination.contains('/') && metric.parse::<u64>().is_ok() =>
+                {
+                    Some(RouteEntry {
+                        destination: destination.to_string(),
+                        gateway: gateway.to_string(),
+                        interface: interface.to_string(),
+                        metric: metric.parse().ok(),
+                    })
+                }
+                _ => None,
+            },
+        )
+        .collect();
+    if !table.is_empty() {
+        return table;
+    }
+
+    let mut routes = Vec::new();
+    let mut current: Option<RouteEntry> = None;
+    for (key, value) in key_values(text) {
+        if key == "destination" {
+            routes.extend(current.take());
+            current = Some(RouteEntry {
+                destination: value.to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+        let Some(route) = current.as_mut() else {
+            continue;
+        };
+        match key.as_str() {
+            "gateway" => route.gateway = value.to_string(),
+            "interface" => route.interface = value.to_string(),
+            "metric" => route.metric = value.parse().ok(),
+            _ => {}
+        }
+    }
+    routes.extend(current);
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_rendering() {
+        let cmd = NdmCommand::NoDefaultRoute {
+            name: "OpkgTun0".into(),
+            ipv6: true,
+        };
+        assert_eq!(cmd.to_string(), "no ipv6 route default OpkgTun0");
+        let route = NdmCommand::Route {
+            name: "OpkgTun1".into(),
+            prefix: "10.1.2.3/16".into(),
+        };
+        assert_eq!(
+            route.to_string(),
+            "ip route 10.1.0.0 255.255.0.0 OpkgTun1 auto"
+        );
+        assert_eq!(
+            serde_json::to_string(&route.rci_request()).unwrap(),
+            r#"{"ip":{"route":{"network":"10.1.0.0","mask":"255.255.0.0","interface":"OpkgTun1","auto":true}}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&cmd.rci_request()).unwrap(),
+            r#"{"ipv6":{"route":{"default":true,"interface":"OpkgTun0","no":true}}}"#
+        );
+        let dns = NdmCommand::NoNameServer(NameServer {
+            address: "1.1.1.1".into(),
+            domain: None,
+            interface: Some("OpkgTun0".into()),
+        });
+        assert_eq!(dns.to_string(), "no ip name-server 1.1.1.1 on OpkgTun0");
+        assert_eq!(
+            serde_json::to_string(&dns.rci_request()).unwrap(),
+            r#"{"ip":{"name-server":{"address":"1.1.1.1","interface":"OpkgTun0","no":true}}}"#
+        );
+    }
+
+    #[test]
+    fn test_ndmc_error_classification() {
+        let err = NdmError::from_ndmc(
+            "interface OpkgTun0 up",
+            1,
+            "Command::Base: error (0xcffd0060).",
+        );
+        assert_eq!(err.code, "0xcffd0060");
+        assert!(err.transient);
+        let err = NdmError::from_ndmc("ip route default X", 1, "invalid interface");
+        assert!(!err.transient);
+
+        let err = NdmError::from(RciError {
+            kind: RciErrorKind::Rejected,
+            code: "7405600".into(),
+            ident: "Core::Configurator".into(),
+            message: "unable to find OpkgTun0".into(),
+        });
+        assert!(err.transient);
+        let err = NdmError::from(RciError {
+            kind: RciErrorKind::Rejected,
+            code: (0xcffd0060u32).to_string(),
+            ident: "Core::Base".into(),
+            message: "error".into(),
+        });
+        assert!(err.transient);
+        let err = NdmError::from(RciError {
+            kind: RciErrorKind::Rejected,
+            code: "7405601".into(),
+            ident: "Core::Configurator".into(),
+            message: "invalid interface".into(),
+        });
+        assert!(!err.transient);
+    }
+
+    #[test]
+    fn test_parse_show_output() {
+        let iface = parse_interface(
+            "id: OpkgTun0\n index: 0\n type: OpkgTun\n link: up\n connected: yes\n state: up\n mtu: 1280\n address: 172.16.219.2\n mask: 255.255.255.255\n uptime: 42\n security-level: public\n",
+        );
+        assert!(iface.is_up());
+        assert_eq!(iface.mtu, Some(1280));
+        assert_eq!(iface.address, "172.16.219.2");
+
+        let routes = parse_routes(concat!(
+            "================================================================================\n",
+            "         Destination         Gateway        Interface  Metric\n",
+            "================================================================================\n",
+            "           0.0.0.0/0         0.0.0.0         OpkgTun0       0\n",
+            "           0.0.0.0/0        10.0.0.1 GigabitEthernet1       0\n",
+            "          10.0.0.0/24         0.0.0.0 GigabitEthernet1       0\n",
+            "      192.168.1.0/24         0.0.0.0          Bridge0       0\n",
+            "================================================================================\n",
+        ));
+        assert_eq!(routes.len(), 4);
+        assert!(routes[0].is_default());
+        assert_eq!(routes[0].interface, "OpkgTun0");
+        assert_eq!(routes[1].gateway, "10.0.0.1");
+        assert_eq!(routes[3].interface, "Bridge0");
+        assert!(parse_routes("Command::Base: error: unknown command").is_empty());
+    }
+}
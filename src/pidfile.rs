@@ -0,0 +1,71 @@
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+
+/// Exclusive lock on the wrapper's PID file, held for the life of the
+/// process. The file also holds the PID for init scripts.
+pub struct InstanceLock {
+    _file: Flock<File>,
+}
+
+impl InstanceLock {
+    /// Lock `path` and write this process's PID into it. Fails, naming the
+    /// holder, when another wrapper already holds the lock.
+    pub fn acquire(path: &str) -> Result<Self, String> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| format!("cannot open {}: {}", path, e))?;
+        let mut file = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(file) => file,
+            Err((mut file, Errno::EWOULDBLOCK)) => {
+                let mut content = String::new();
+                let _ = file.read_to_string(&mut content);
+                let holder = content.trim();
+                return Err(if holder.is_empty() {
+                    format!("another instance is already running (lock {})", path)
+                } else {
+                    format!(
+                        "another instance is already running (PID {}, lock {})",
+                        holder, path
+                    )
+                });
+            }
+            Err((_, e)) => return Err(format!("cannot lock {}: {}", path, e)),
+        };
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| writeln!(*file, "{}", std::process::id()))
+            .map_err(|e| format!("cannot write {}: {}", path, e))?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_lock_names_holder() {
+        let path = std::env::temp_dir().join(format!("tt-instance-{}.pid", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+
+        let lock = InstanceLock::acquire(&path).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.trim(), std::process::id().to_string());
+        // A second open file description conflicts even within one process.
+        let err = InstanceLock::acquire(&path).err().unwrap();
+        assert!(err.contains(&format!("PID {}", std::process::id())));
+        drop(lock);
+        assert!(InstanceLock::acquire(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,258 @@
+use crate::config::{ProbeSettings, RoutingSettings};
+use nix::sys::socket::{self, sockopt, AddressFamily, SockFlag, SockType, SockaddrStorage};
+use nix::sys::time::{TimeVal, TimeValLike};
+use serde::Serialize;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::os::fd::{AsFd, AsRawFd};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+impl std::fmt::Display for ProbeSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeSettings::Http { url, .. } => write!(f, "http {}", url),
+            ProbeSettings::Dns { name, server } => write!(f, "dns {} @{}", name, server),
+            ProbeSettings::Tcp { address } => write!(f, "tcp {}", address),
+            ProbeSettings::Icmp { host } => write!(f, "icmp {}", host),
+        }
+    }
+}
+
+/// Outcome of one probe in a watchdog round.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub probe: String,
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+/// All probes of one watchdog check, sent out of `dev`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProbeRound {
+    pub dev: String,
+    pub quorum: usize,
+    pub passed: usize,
+    pub healthy: bool,
+    /// Unix time of the round, seconds.
+    pub checked_at: u64,
+    pub results: Vec<ProbeResult>,
+}
+
+/// Configured probes, or a single HTTP probe of `watchdog_check_url` when
+/// none are set.
+pub fn from_settings(routing: &RoutingSettings) -> Vec<ProbeSettings> {
+    if !routing.watchdog_probes.is_empty() {
+        return routing.watchdog_probes.clone();
+    }
+    vec![ProbeSettings::Http {
+        url: routing.watchdog_check_url.clone(),
+        expect_status: None,
+        body_contains: String::new(),
+    }]
+}
+
+/// Probes needed for a healthy round: `quorum`, capped at the probe count;
+/// 0 means all of them.
+pub fn effective_quorum(quorum: u32, probes: usize) -> usize {
+    match quorum as usize {
+        0 => probes,
+        q => q.min(probes),
+    }
+}
+
+/// Run every probe concurrently out of `dev` and apply the quorum rule.
+pub fn run_all(probes: &[ProbeSettings], quorum: u32, dev: &str, timeout: Duration) -> ProbeRound {
+    let results: Vec<ProbeResult> = std::thread::scope(|scope| {
+        let handles: Vec<_> = probes
+            .iter()
+            .map(|probe| scope.spawn(move || run_one(probe, dev, timeout)))
+            .collect();
+        handles
+            .into_iter()
+            .zip(probes)
+            .map(|(handle, probe)| {
+                handle.join().unwrap_or_else(|_| ProbeResult {
+                    probe: probe.to_string(),
+                    ok: false,
+                    latency_ms: 0,
+                    error: "probe panicked".into(),
+                })
+            })
+            .collect()
+    });
+    let passed = results.iter().filter(|r| r.ok).count();
+    let quorum = effective_quorum(quorum, results.len());
+    ProbeRound {
+        dev: dev.to_string(),
+        quorum,
+        passed,
+        healthy: passed >= quorum,
+        checked_at: crate::util::unix_now(),
+        results,
+    }
+}
+
+fn run_one(probe: &ProbeSettings, dev: &str, timeout: Duration) -> ProbeResult {
+    let start = Instant::now();
+    let outcome = match probe {
+        ProbeSettings::Http {
+            url,
+            expect_status,
+            body_contains,
+        } => probe_http(url, *expect_status, body_contains, dev, timeout),
+        ProbeSettings::Dns { name, server } => server
+            .parse::<SocketAddr>()
+            .map_err(|e| format!("server '{}': {}", server, e))
+            .and_then(|server| crate::dns::resolve_a(server, name, timeout, Some(dev)))
+            .map(|_| ()),
+        ProbeSettings::Tcp { address } => probe_tcp(address, dev, timeout),
+        ProbeSettings::Icmp { host } => probe_icmp(host, dev, timeout),
+    };
+    let latency_ms = start.elapsed().as_millis() as u64;
+    if let Err(e) = &outcome {
+        log::debug!("[watchdog] probe {} failed: {}", probe, e);
+    }
+    ProbeResult {
+        probe: probe.to_string(),
+        ok: outcome.is_ok(),
+        latency_ms,
+        error: outcome.err().unwrap_or_default(),
+    }
+}
+
+/// Restrict a socket to one network interface (`SO_BINDTODEVICE`).
+pub fn bind_to_device<F: AsFd>(fd: &F, dev: &str) -> Result<(), String> {
+    socket::setsockopt(fd, sockopt::BindToDevice, &std::ffi::OsString::from(dev))
+        .map_err(|e| format!("bind to {}: {}", dev, e))
+}
+
+fn status_matches(status: u16, expect: Option<u16>) -> bool {
+    match expect {
+        Some(expected) => status == expected,
+        None => (200..400).contains(&status),
+    }
+}
+
+fn probe_http(
+    url: &str,
+    expect_status: Option<u16>,
+    body_contains: &str,
+    dev: &str,
+    timeout: Duration,
+) -> Result<(), String> {
+    let timeout_secs = timeout.as_secs().max(1);
+    let body_target = if body_contains.is_empty() {
+        "/dev/null"
+    } else {
+        "-"
+    };
+    let output = Command::new("curl")
+        .args(["--interface", dev, "--connect-timeout"])
+        .arg(timeout_secs.to_string())
+        .arg("--max-time")
+        .arg((timeout_secs + 2).to_string())
+        .args(["-sS", "-o", body_target, "-w", "\n%{http_code}", url])
+        .output();
+    let (status, body) = match output {
+        Ok(out) => {
+            let text = String::from_utf8_lossy(&out.stdout).into_owned();
+            let (body, code) = text.rsplit_once('\n').unwrap_or(("", text.as_str()));
+            let status: u16 = code.trim().parse().unwrap_or(0);
+            if status == 0 {
+                return Err(format!(
+                    "curl: {}",
+                    String::from_utf8_lossy(&out.stderr).trim()
+                ));
+            }
+            (status, body.to_string())
+        }
+        // No curl: ureq cannot pick the interface, but still tells a dead
+        // upstream from a live one.
+        Err(_) => match ureq::get(url).timeout(timeout).call() {
+            Ok(resp) => {
+                let status = resp.status();
+                (status, resp.into_string().unwrap_or_default())
+            }
+            Err(ureq::Error::Status(code, resp)) => (code, resp.into_string().unwrap_or_default()),
+            Err(e) => return Err(e.to_string()),
+        },
+    };
+    if !status_matches(status, expect_status) {
+        return Err(format!("unexpected status {}", status));
+    }
+    if !body_contains.is_empty() && !body.contains(body_contains) {
+        return Err(format!("body does not contain '{}'", body_contains));
+    }
+    Ok(())
+}
+
+fn probe_tcp(address: &str, dev: &str, timeout: Duration) -> Result<(), String> {
+    let target = address
+        .to_socket_addrs()
+        .map_err(|e| format!("{}: {}", address, e))?
+        .next()
+        .ok_or_else(|| format!("{}: no address", address))?;
+    let family = if target.is_ipv6() {
+        AddressFamily::Inet6
+    } else {
+        AddressFamily::Inet
+    };
+    let fd = socket::socket(family, SockType::Stream, SockFlag::SOCK_CLOEXEC, None)
+        .map_err(|e| format!("socket: {}", e))?;
+    bind_to_device(&fd, dev)?;
+    // Linux applies the send timeout to a blocking connect().
+    let tv = TimeVal::milliseconds(timeout.as_millis() as i64);
+    socket::setsockopt(&fd, sockopt::SendTimeout, &tv).map_err(|e| e.to_string())?;
+    socket::connect(fd.as_raw_fd(), &SockaddrStorage::from(target))
+        .map_err(|e| format!("connect {}: {}", target, e))
+}
+
+fn probe_icmp(host: &str, dev: &str, timeout: Duration) -> Result<(), String> {
+    let wait = timeout.as_secs().max(1).to_string();
+    let output = Command::new("ping")
+        .args(["-c", "1", "-W", &wait, "-I", dev, host])
+        .output()
+        .map_err(|e| format!("ping: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("no reply from {}", host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_settings_and_quorum() {
+        let probes: Vec<ProbeSettings> = serde_json::from_str(
+            r#"[
+                {"type": "http", "url": "https://example.com/health", "expect_status": 204},
+                {"type": "dns", "name": "example.com"},
+                {"type": "tcp", "address": "1.1.1.1:443"},
+                {"type": "icmp", "host": "8.8.8.8"}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            probes.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+            vec![
+                "http https://example.com/health",
+                "dns example.com @1.1.1.1:53",
+                "tcp 1.1.1.1:443",
+                "icmp 8.8.8.8",
+            ]
+        );
+        assert_eq!(effective_quorum(0, 4), 4);
+        assert_eq!(effective_quorum(2, 4), 2);
+        assert_eq!(effective_quorum(9, 4), 4);
+        assert!(status_matches(301, None));
+        assert!(!status_matches(200, Some(204)));
+
+        let fallback = from_settings(&RoutingSettings::default());
+        assert!(matches!(&fallback[..], [ProbeSettings::Http { .. }]));
+    }
+}
@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::process::{Child, ExitStatus};
+use std::time::Duration;
+
+/// How long a client gets to exit on SIGTERM before it is killed.
+const TERM_TIMEOUT: Duration = Duration::from_secs(5);
+const TERM_POLL: Duration = Duration::from_millis(100);
+
+/// The running trusttunnel_client: spawned by this wrapper, or adopted from
+/// a previous run.
+pub enum ClientProcess {
+    Spawned(Child),
+    /// Not our child, so it is watched through `/proc` and its exit status
+    /// cannot be collected. The command line is checked on every poll, so a
+    /// reused PID is not mistaken for the client.
+    Adopted {
+        pid: u32,
+        bin: String,
+        config: String,
+    },
+}
+
+/// Result of polling a client process.
+#[derive(Debug)]
+pub enum Poll {
+    Running,
+    /// Exit status, when it could be collected.
+    Exited(Option<ExitStatus>),
+}
+
+impl ClientProcess {
+    pub fn adopted(pid: u32, bin: &str, config: &str) -> Self {
+        Self::Adopted {
+            pid,
+            bin: bin.to_string(),
+            config: config.to_string(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Spawned(child) => child.id(),
+            Self::Adopted { pid, .. } => *pid,
+        }
+    }
+
+    pub fn poll(&mut self) -> std::io::Result<Poll> {
+        match self {
+            Self::Spawned(child) => Ok(match child.try_wait()? {
+                Some(status) => Poll::Exited(Some(status)),
+                None => Poll::Running,
+            }),
+            Self::Adopted { pid, bin, config } => Ok(if is_client(*pid, bin, config) {
+                Poll::Running
+            } else {
+                Poll::Exited(None)
+            }),
+        }
+    }
+
+    /// SIGTERM, then SIGKILL if the client is still running after
+    /// `TERM_TIMEOUT`. Returns the exit status when it could be collected.
+    pub fn terminate(&mut self) -> Option<ExitStatus> {
+        if let Ok(Poll::Exited(status)) = self.poll() {
+            return status;
+        }
+        let pid = nix::unistd::Pid::from_raw(self.id() as i32);
+        let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGTERM);
+        let mut waited = Duration::ZERO;
+        while waited < TERM_TIMEOUT {
+            if let Ok(Poll::Exited(status)) = self.poll() {
+                return status;
+            }
+            std::thread::sleep(TERM_POLL);
+            waited += TERM_POLL;
+        }
+        match self {
+            Self::Spawned(child) => {
+                let _ = child.kill();
+                child.wait().ok()
+            }
+            Self::Adopted { .. } => {
+                let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL);
+                None
+            }
+        }
+    }
+}
+
+/// Whether `pid` exists and is not a zombie.
+fn is_alive(pid: u32) -> bool {
+    let Ok(stat) = std::fs::read_to_string(format!("/proc/{}/stat", pid)) else {
+        return false;
+    };
+    // The state follows the parenthesised command name, which may itself
+    // contain spaces or parentheses.
+    let state = stat
+        .rsplit_once(')')
+        .and_then(|(_, rest)| rest.trim_start().chars().next());
+    !matches!(state, None | Some('Z') | Some('X'))
+}
+
+fn cmdline(pid: u32) -> Option<Vec<String>> {
+    let raw = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    Some(
+        raw.split(|b| *b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect(),
+    )
+}
+
+/// Whether `args` run `bin` with `config`, so each tunnel only finds its
+/// own client.
+fn matches_client(args: &[String], bin: &str, config: &str) -> bool {
+    let name = |p: &str| Path::new(p).file_name().map(|n| n.to_owned());
+    let Some(first) = args.first() else {
+        return false;
+    };
+    name(first) == name(bin)
+        && args
+            .windows(2)
+            .any(|pair| pair[0] == "--config" && pair[1] == config)
+}
+
+fn is_client(pid: u32, bin: &str, config: &str) -> bool {
+    is_alive(pid) && cmdline(pid).is_some_and(|args| matches_client(&args, bin, config))
+}
+
+/// A live client for `config` left by a previous run: the PID in `pidfile`
+/// if it still runs the client, otherwise the first match in `/proc`.
+pub fn find_orphan(pidfile: &str, bin: &str, config: &str) -> Option<u32> {
+    let own = std::process::id();
+    let recorded = std::fs::read_to_string(pidfile)
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok());
+    if let Some(pid) = recorded.filter(|pid| *pid != own && is_client(*pid, bin, config)) {
+        return Some(pid);
+    }
+    std::fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .find(|pid| *pid != own && is_client(*pid, bin, config))
+}
+
+pub fn write_pidfile(path: &str, pid: u32) {
+    if path.is_empty() {
+        return;
+    }
+    if let Some(parent) = Path::new(path).parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    if let Err(e) = std::fs::write(path, format!("{}\n", pid)) {
+        log::warn!("[tunnel] cannot write {}: {}", path, e);
+    }
+}
+
+pub fn remove_pidfile(path: &str) {
+    if !path.is_empty() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_client_and_liveness() {
+        let args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        let bin = "/opt/bin/trusttunnel_client";
+        let config = "/opt/etc/trusttunnel/trusttunnel_client.toml";
+        assert!(matches_client(
+            &args("/opt/bin/trusttunnel_client --config /opt/etc/trusttunnel/trusttunnel_client.toml --loglevel info"),
+            bin,
+            config
+        ));
+        // Another instance's client, and an unrelated process.
+        assert!(!matches_client(
+            &args("trusttunnel_client --config /opt/etc/trusttunnel/trusttunnel_client1.toml"),
+            bin,
+            config
+        ));
+        assert!(!matches_client(
+            &args("/bin/sh --config /opt/etc/trusttunnel/trusttunnel_client.toml"),
+            bin,
+            config
+        ));
+
+        assert!(is_alive(std::process::id()));
+        assert!(!is_alive(u32::MAX));
+
+        // A live PID that no longer runs the client reads as exited.
+        let mut reused = ClientProcess::adopted(std::process::id(), bin, config);
+        assert!(matches!(reused.poll(), Ok(Poll::Exited(None))));
+    }
+}
@@ -0,0 +1,310 @@
+use crate::config::QualitySettings;
+use crate::util::{atomic_write, unix_now};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Minute buckets kept per target: 24 hours.
+const MAX_MINUTES: usize = 24 * 60;
+
+/// Raw counters of one target for one minute; several watchdog samples may
+/// land in the same bucket.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct MinuteBucket {
+    /// Unix time of the minute start, seconds.
+    minute: u64,
+    sent: u32,
+    received: u32,
+    rtt_sum_ms: f64,
+    rtt_count: u32,
+    jitter_sum_ms: f64,
+    jitter_count: u32,
+}
+
+/// One minute of quality data as served by the API.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityPoint {
+    pub time: u64,
+    pub rtt_ms: Option<f64>,
+    pub loss_pct: f64,
+    pub jitter_ms: Option<f64>,
+}
+
+impl From<&MinuteBucket> for QualityPoint {
+    fn from(b: &MinuteBucket) -> Self {
+        let avg = |sum: f64, n: u32| (n > 0).then(|| (sum / n as f64 * 100.0).round() / 100.0);
+        let loss_pct = if b.sent == 0 {
+            0.0
+        } else {
+            (100.0 * (b.sent - b.received.min(b.sent)) as f64 / b.sent as f64 * 10.0).round() / 10.0
+        };
+        Self {
+            time: b.minute,
+            rtt_ms: avg(b.rtt_sum_ms, b.rtt_count),
+            loss_pct,
+            jitter_ms: avg(b.jitter_sum_ms, b.jitter_count),
+        }
+    }
+}
+
+/// Result of one `ping` run.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PingStats {
+    sent: u32,
+    received: u32,
+    rtts_ms: Vec<f64>,
+}
+
+/// Per-target RTT/loss/jitter series of one tunnel.
+pub struct QualityMonitor {
+    settings: QualitySettings,
+    series: Mutex<BTreeMap<String, VecDeque<MinuteBucket>>>,
+}
+
+impl QualityMonitor {
+    /// Create the monitor, loading a series saved by a previous run.
+    pub fn new(settings: &QualitySettings) -> Self {
+        let series = if settings.persist_path.is_empty() {
+            BTreeMap::new()
+        } else {
+            std::fs::read_to_string(&settings.persist_path)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+                .unwrap_or_default()
+        };
+        Self {
+            settings: settings.clone(),
+            series: Mutex::new(series),
+        }
+    }
+
+    /// Time between samples, clamped so every minute gets one.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.settings.interval.clamp(5, 60))
+    }
+
+    /// Ping every target out of `dev` and add the results to the current minute.
+    pub fn sample(&self, dev: &str) {
+        let pings = self.settings.pings.max(1);
+        let results: Vec<(String, Option<PingStats>)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .settings
+                .targets
+                .iter()
+                .map(|target| (target, scope.spawn(move || ping(target, dev, pings))))
+                .collect();
+            handles
+                .into_iter()
+                .map(|(target, h)| (target.clone(), h.join().ok().flatten()))
+                .collect()
+        });
+        let now = unix_now();
+        let mut rolled_over = false;
+        for (target, stats) in results {
+            match stats {
+                Some(stats) => rolled_over |= self.record(&target, now, &stats),
+                None => log::debug!("[quality] ping {} via {} unavailable", target, dev),
+            }
+        }
+        if rolled_over {
+            self.persist();
+        }
+    }
+
+    /// Add `stats` to the bucket of `now`. Returns true when a new minute started.
+    fn record(&self, target: &str, now: u64, stats: &PingStats) -> bool {
+        let minute = now - now % 60;
+        let mut series = self.series.lock().unwrap();
+        let buckets = series.entry(target.to_string()).or_default();
+        let new_minute = buckets.back().map(|b| b.minute) != Some(minute);
+        if new_minute {
+            buckets.push_back(MinuteBucket {
+                minute,
+                ..Default::default()
+            });
+        }
+        while buckets.len() > MAX_MINUTES
+            || buckets
+                .front()
+                .is_some_and(|b| b.minute + (MAX_MINUTES as u64) * 60 <= minute)
+        {
+            buckets.pop_front();
+        }
+        let bucket = buckets.back_mut().expect("bucket just ensured");
+        bucket.sent += stats.sent;
+        bucket.received += stats.received;
+        bucket.rtt_sum_ms += stats.rtts_ms.iter().sum::<f64>();
+        bucket.rtt_count += stats.rtts_ms.len() as u32;
+        for pair in stats.rtts_ms.windows(2) {
+            bucket.jitter_sum_ms += (pair[1] - pair[0]).abs();
+            bucket.jitter_count += 1;
+        }
+        new_minute
+    }
+
+    fn persist(&self) {
+        if self.settings.persist_path.is_empty() {
+            return;
+        }
+        let content = match serde_json::to_string(&*self.series.lock().unwrap()) {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("[quality] cannot serialize series: {}", e);
+                return;
+            }
+        };
+        let path = std::path::Path::new(&self.settings.persist_path);
+        if let Err(e) = atomic_write(path, content) {
+            log::warn!("[quality] cannot save {}: {}", path.display(), e);
+        }
+    }
+
+    /// Points of the last `minutes` minutes per target, oldest first.
+    pub fn series(&self, minutes: u64) -> BTreeMap<String, Vec<QualityPoint>> {
+        let since = unix_now().saturating_sub(minutes * 60);
+        self.series
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(target, buckets)| {
+                let points = buckets
+                    .iter()
+                    .filter(|b| b.minute + 60 > since)
+                    .map(QualityPoint::from)
+                    .collect();
+                (target.clone(), points)
+            })
+            .collect()
+    }
+
+    /// Loss over the last `loss_failure_minutes`, if it is above
+    /// `loss_failure_pct` for the whole window. Only targets with a bucket
+    /// for every minute of the window count, so a gap (a disconnect, or a
+    /// series loaded from an earlier run) restarts the window.
+    pub fn sustained_loss(&self) -> Option<f64> {
+        self.sustained_loss_at(unix_now())
+    }
+
+    fn sustained_loss_at(&self, now: u64) -> Option<f64> {
+        let threshold = self.settings.loss_failure_pct;
+        if threshold == 0 {
+            return None;
+        }
+        let minutes = self.settings.loss_failure_minutes.max(1) as u64;
+        let current = now - now % 60;
+        let window_start = current.saturating_sub((minutes - 1) * 60);
+        let series = self.series.lock().unwrap();
+        let (mut sent, mut received) = (0u64, 0u64);
+        for buckets in series.values() {
+            let window: Vec<&MinuteBucket> = buckets
+                .iter()
+                .filter(|b| b.minute >= window_start && b.minute <= current)
+                .collect();
+            // Buckets are unique per minute, so a full count means no gaps.
+            if window.len() as u64 != minutes {
+                continue;
+            }
+            for b in window {
+                sent += b.sent as u64;
+                received += b.received.min(b.sent) as u64;
+            }
+        }
+        if sent == 0 {
+            return None;
+        }
+        let loss = 100.0 * (sent - received) as f64 / sent as f64;
+        (loss > threshold as f64).then_some(loss)
+    }
+}
+
+/// `ping -c <count> -I <dev> <target>`; `None` when ping cannot be run.
+fn ping(target: &str, dev: &str, count: u32) -> Option<PingStats> {
+    let output = Command::new("ping")
+        .args(["-c", &count.to_string(), "-W", "2", "-I", dev, target])
+        .output()
+        .ok()?;
+    let stats = parse_ping(&String::from_utf8_lossy(&output.stdout));
+    (stats.sent > 0).then_some(stats)
+}
+
+/// Parse busybox or iputils `ping` output.
+fn parse_ping(output: &str) -> PingStats {
+    let mut stats = PingStats::default();
+    for line in output.lines() {
+        if let Some(pos) = line.find("time=") {
+            let value = line[pos + 5..].trim_start();
+            let end = value
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(value.len());
+            if let Ok(rtt) = value[..end].parse() {
+                stats.rtts_ms.push(rtt);
+            }
+        } else if line.contains("transmitted") {
+            let numbers: Vec<u32> = line
+                .split(|c: char| !c.is_ascii_digit())
+                .filter_map(|n| n.parse().ok())
+                .collect();
+            if let [sent, received, ..] = numbers[..] {
+                stats.sent = sent;
+                stats.received = received;
+            }
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ping() {
+        let busybox = "PING 1.1.1.1 (1.1.1.1): 56 data bytes\n64 bytes from 1.1.1.1: seq=0 ttl=57 time=10.500 ms\n64 bytes from 1.1.1.1: seq=2 ttl=57 time=14.500 ms\n\n--- 1.1.1.1 ping statistics ---\n3 packets transmitted, 2 packets received, 33% packet loss\nround-trip min/avg/max = 10.500/12.500/14.500 ms\n";
+        assert_eq!(
+            parse_ping(busybox),
+            PingStats {
+                sent: 3,
+                received: 2,
+                rtts_ms: vec![10.5, 14.5],
+            }
+        );
+        let iputils = "64 bytes from 8.8.8.8: icmp_seq=1 ttl=117 time=21.3 ms\n4 packets transmitted, 1 received, 75% packet loss, time 3004ms\n";
+        let stats = parse_ping(iputils);
+        assert_eq!((stats.sent, stats.received), (4, 1));
+        assert_eq!(stats.rtts_ms, vec![21.3]);
+    }
+
+    #[test]
+    fn test_minute_buckets_and_sustained_loss() {
+        let monitor = QualityMonitor::new(&QualitySettings {
+            loss_failure_pct: 20,
+            loss_failure_minutes: 2,
+            ..Default::default()
+        });
+        let lossy = PingStats {
+            sent: 5,
+            received: 3,
+            rtts_ms: vec![10.0, 14.0, 12.0],
+        };
+        let t0 = 1_760_000_040;
+        assert!(monitor.record("1.1.1.1", t0, &lossy));
+        assert!(!monitor.record("1.1.1.1", t0 + 10, &lossy));
+        let point = QualityPoint::from(&monitor.series.lock().unwrap()["1.1.1.1"][0]);
+        assert_eq!(point.loss_pct, 40.0);
+        assert_eq!(point.rtt_ms, Some(12.0));
+        assert_eq!(point.jitter_ms, Some(3.0));
+
+        // One minute of data does not cover a two-minute window yet.
+        assert_eq!(monitor.sustained_loss_at(t0), None);
+        monitor.record("1.1.1.1", t0 + 60, &lossy);
+        assert_eq!(monitor.sustained_loss_at(t0 + 60), Some(40.0));
+
+        // A gap (no sample in the previous minute) restarts the window.
+        monitor.record("1.1.1.1", t0 + 180, &lossy);
+        assert_eq!(monitor.sustained_loss_at(t0 + 180), None);
+        monitor.record("1.1.1.1", t0 + 240, &lossy);
+        assert_eq!(monitor.sustained_loss_at(t0 + 240), Some(40.0));
+    }
+}
@@ -0,0 +1,367 @@
+use crate::config::{QuotaAction, QuotaPeriod, QuotaSettings};
+use crate::util::{atomic_write, unix_now};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Usage is written at most this often while it grows.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+const DAY: u64 = 86_400;
+
+/// Usage of the current period, as saved to `persist_path`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Usage {
+    /// Unix time the period started, seconds.
+    period_start: u64,
+    used_bytes: u64,
+    /// Highest `warn_pct` already reported in this period.
+    warned_pct: u32,
+    exhausted_reported: bool,
+    /// Connecting was allowed past the limit for the rest of the period.
+    overridden: bool,
+}
+
+/// Something `Quota::update` noticed that the tunnel should act on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuotaEvent {
+    PeriodReset,
+    Warning(u32),
+    Exhausted,
+}
+
+/// Quota state served by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaStatus {
+    pub limit_bytes: u64,
+    pub used_bytes: u64,
+    pub remaining_bytes: u64,
+    pub used_pct: f64,
+    pub period_start: u64,
+    pub period_end: u64,
+    pub action: QuotaAction,
+    pub exhausted: bool,
+    pub overridden: bool,
+}
+
+struct QuotaInner {
+    usage: Usage,
+    /// Last traffic total seen, to turn the running total into deltas.
+    last_total: u64,
+    dirty: bool,
+    last_persist: Instant,
+}
+
+/// Data allowance of one tunnel.
+pub struct Quota {
+    settings: QuotaSettings,
+    inner: Mutex<QuotaInner>,
+}
+
+impl Quota {
+    /// `None` unless the quota is enabled with a limit.
+    pub fn new(settings: &QuotaSettings) -> Option<Self> {
+        if !settings.enabled || settings.limit == 0 {
+            return None;
+        }
+        let usage = std::fs::read_to_string(&settings.persist_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Some(Self {
+            settings: settings.clone(),
+            inner: Mutex::new(QuotaInner {
+                usage,
+                last_total: 0,
+                dirty: false,
+                last_persist: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn action(&self) -> QuotaAction {
+        self.settings.action
+    }
+
+    /// Account traffic up to `total_bytes`, the tunnel's running byte total
+    /// since the wrapper started.
+    pub fn update(&self, total_bytes: u64) -> Vec<QuotaEvent> {
+        let events = self.update_at(total_bytes, unix_now());
+        let mut inner = self.inner.lock().unwrap();
+        if inner.dirty && (!events.is_empty() || inner.last_persist.elapsed() >= PERSIST_INTERVAL) {
+            self.persist_locked(&mut inner);
+        }
+        events
+    }
+
+    fn update_at(&self, total_bytes: u64, now: u64) -> Vec<QuotaEvent> {
+        let mut events = Vec::new();
+        let mut inner = self.inner.lock().unwrap();
+        let delta = total_bytes.saturating_sub(inner.last_total);
+        inner.last_total = total_bytes;
+
+        let (period_start, _) = period_bounds(&self.settings, now);
+        if inner.usage.period_start != period_start {
+            if inner.usage.period_start != 0 {
+                events.push(QuotaEvent::PeriodReset);
+            }
+            inner.usage = Usage {
+                period_start,
+                ..Default::default()
+            };
+            inner.dirty = true;
+        }
+        if delta == 0 {
+            return events;
+        }
+        inner.usage.used_bytes += delta;
+        inner.dirty = true;
+
+        let limit = self.settings.limit;
+        let used = inner.usage.used_bytes;
+        let crossed = self
+            .settings
+            .warn_pct
+            .iter()
+            .copied()
+            .filter(|pct| *pct > inner.usage.warned_pct && *pct < 100)
+            .filter(|pct| used as u128 * 100 >= limit as u128 * *pct as u128)
+            .max();
+        if let Some(pct) = crossed {
+            inner.usage.warned_pct = pct;
+            events.push(QuotaEvent::Warning(pct));
+        }
+        if used >= limit && !inner.usage.exhausted_reported {
+            inner.usage.exhausted_reported = true;
+            events.push(QuotaEvent::Exhausted);
+        }
+        events
+    }
+
+    /// The limit is reached and connecting was not overridden.
+    pub fn exhausted(&self) -> bool {
+        self.exhausted_locked(&self.inner.lock().unwrap())
+    }
+
+    /// Exhausted, and configured with `action`. Only `disconnect` refuses a
+    /// new connection and only `block` holds it down; `log` never stops one.
+    pub fn exhausted_with(&self, action: QuotaAction) -> bool {
+        self.settings.action == action && self.exhausted()
+    }
+
+    fn exhausted_locked(&self, inner: &QuotaInner) -> bool {
+        let (period_start, _) = period_bounds(&self.settings, unix_now());
+        inner.usage.period_start == period_start
+            && inner.usage.used_bytes >= self.settings.limit
+            && !inner.usage.overridden
+    }
+
+    /// Allow connecting past the limit until the period ends. Only takes
+    /// effect once the quota is exhausted; returns whether it did.
+    pub fn set_override(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if !self.exhausted_locked(&inner) {
+            return false;
+        }
+        inner.usage.overridden = true;
+        self.persist_locked(&mut inner);
+        true
+    }
+
+    /// Start the current period over with nothing used.
+    pub fn reset(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.usage = Usage {
+            period_start: period_bounds(&self.settings, unix_now()).0,
+            ..Default::default()
+        };
+        self.persist_locked(&mut inner);
+    }
+
+    pub fn status(&self) -> QuotaStatus {
+        let (period_start, period_end) = period_bounds(&self.settings, unix_now());
+        let inner = self.inner.lock().unwrap();
+        let used = if inner.usage.period_start == period_start {
+            inner.usage.used_bytes
+        } else {
+            0
+        };
+        let limit = self.settings.limit;
+        QuotaStatus {
+            limit_bytes: limit,
+            used_bytes: used,
+            remaining_bytes: limit.saturating_sub(used),
+            used_pct: (used as f64 * 1000.0 / limit as f64).round() / 10.0,
+            period_start,
+            period_end,
+            action: self.settings.action,
+            exhausted: used >= limit,
+            overridden: inner.usage.overridden && inner.usage.period_start == period_start,
+        }
+    }
+
+    /// Save pending usage now.
+    pub fn persist(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.dirty {
+            self.persist_locked(&mut inner);
+        }
+    }
+
+    fn persist_locked(&self, inner: &mut QuotaInner) {
+        inner.dirty = false;
+        inner.last_persist = Instant::now();
+        if self.settings.persist_path.is_empty() {
+            return;
+        }
+        let content = match serde_json::to_string(&inner.usage) {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("[quota] cannot serialize usage: {}", e);
+                return;
+            }
+        };
+        let path = std::path::Path::new(&self.settings.persist_path);
+        if let Err(e) = atomic_write(path, content) {
+            log::warn!("[quota] cannot save {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Start and end (Unix seconds, UTC) of the period containing `now`.
+fn period_bounds(settings: &QuotaSettings, now: u64) -> (u64, u64) {
+    match settings.period {
+        QuotaPeriod::Daily => {
+            let start = now - now % DAY;
+            (start, start + DAY)
+        }
+        QuotaPeriod::Monthly => {
+            let reset_day = settings.reset_day.clamp(1, 28);
+            let (mut year, mut month, day) = civil_from_days((now / DAY) as i64);
+            if day < reset_day {
+                (year, month) = if month == 1 {
+                    (year - 1, 12)
+                } else {
+                    (year, month - 1)
+                };
+            }
+            let (next_year, next_month) = if month == 12 {
+                (year + 1, 1)
+            } else {
+                (year, month + 1)
+            };
+            let start = days_from_civil(year, month, reset_day) as u64 * DAY;
+            let end = days_from_civil(next_year, next_month, reset_day) as u64 * DAY;
+            (start, end)
+        }
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// (year, month, day) of a day count since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_monthly_period_bounds() {
+        let settings = QuotaSettings {
+            reset_day: 15,
+            ..Default::default()
+        };
+        // 2026-10-18 12:00 UTC: period runs 2026-10-15 .. 2026-11-15.
+        assert_eq!(
+            period_bounds(&settings, 1_792_324_800),
+            (1_792_022_400, 1_794_700_800)
+        );
+        // 2026-01-03: the period started on 2025-12-15.
+        let (start, end) = period_bounds(&settings, 1_767_398_400);
+        assert_eq!(civil_from_days((start / DAY) as i64), (2025, 12, 15));
+        assert_eq!(civil_from_days((end / DAY) as i64), (2026, 1, 15));
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+    }
+
+    #[test]
+    fn test_usage_warnings_and_exhaustion() {
+        let quota = Quota::new(&QuotaSettings {
+            enabled: true,
+            limit: 1000,
+            period: QuotaPeriod::Daily,
+            persist_path: String::new(),
+            ..Default::default()
+        })
+        .unwrap();
+        let day = 1_792_281_600;
+        assert_eq!(quota.update_at(500, day + 10), vec![]);
+        assert_eq!(
+            quota.update_at(960, day + 20),
+            vec![QuotaEvent::Warning(95)]
+        );
+        assert_eq!(quota.update_at(1200, day + 30), vec![QuotaEvent::Exhausted]);
+        assert_eq!(quota.update_at(1300, day + 40), vec![]);
+        // The next day starts from zero.
+        assert_eq!(
+            quota.update_at(1400, day + DAY + 5),
+            vec![QuotaEvent::PeriodReset]
+        );
+        assert_eq!(quota.inner.lock().unwrap().usage.used_bytes, 100);
+    }
+
+    #[test]
+    fn test_override_only_when_exhausted() {
+        let quota = Quota::new(&QuotaSettings {
+            enabled: true,
+            limit: 1000,
+            period: QuotaPeriod::Daily,
+            persist_path: String::new(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(!quota.set_override());
+        assert!(!quota.inner.lock().unwrap().usage.overridden);
+        quota.update(1200);
+        assert!(quota.exhausted());
+        assert!(quota.set_override());
+        assert!(!quota.exhausted());
+    }
+
+    #[test]
+    fn test_exhausted_log_quota_does_not_stop_the_tunnel() {
+        let quota = Quota::new(&QuotaSettings {
+            enabled: true,
+            limit: 1000,
+            period: QuotaPeriod::Daily,
+            action: QuotaAction::Log,
+            persist_path: String::new(),
+            ..Default::default()
+        })
+        .unwrap();
+        quota.update(1200);
+        assert!(quota.exhausted());
+        assert!(quota.exhausted_with(QuotaAction::Log));
+        assert!(!quota.exhausted_with(QuotaAction::Disconnect));
+        assert!(!quota.exhausted_with(QuotaAction::Block));
+    }
+}
@@ -0,0 +1,485 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const RCI_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Top-level RCI request. Serializes to the JSON object NDM expects on
+/// `POST /rci/`, e.g. `{"ip": {"route": {...}}}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Request {
+    Interface(InterfaceRequest),
+    Ip(IpRequest),
+    Ipv6(IpRequest),
+    Show(ShowRequest),
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InterfaceRequest {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub down: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<InterfaceIp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security_level: Option<SecurityLevel>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InterfaceIp {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mtu: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global: Option<IpGlobal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<IpTcp>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IpAddress {
+    pub address: String,
+    pub mask: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IpGlobal {
+    pub auto: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IpTcp {
+    pub adjust_mss: AdjustMss,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdjustMss {
+    pub pmtu: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SecurityLevel {
+    pub public: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IpRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<RouteRequest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_server: Option<NameServerRequest>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NameServerRequest {
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RouteRequest {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub default: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mask: Option<String>,
+    /// IPv6 destination in CIDR form.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    pub interface: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ShowRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub running_config: Option<Empty>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<ShowInterface>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<ShowIp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<ShowIp>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShowInterface {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ShowIp {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<Empty>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name_server: Option<Empty>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Empty {}
+
+/// `show running-config` response body.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RunningConfig {
+    #[serde(default)]
+    pub message: Vec<String>,
+}
+
+/// `show interface <name>` response, also filled from `ndmc` text output.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InterfaceInfo {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub link: String,
+    #[serde(default)]
+    pub connected: String,
+    #[serde(default)]
+    pub state: String,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub mask: String,
+    #[serde(default, deserialize_with = "lenient_u64")]
+    pub mtu: Option<u64>,
+    #[serde(default, deserialize_with = "lenient_u64")]
+    pub uptime: Option<u64>,
+    #[serde(default)]
+    pub security_level: String,
+}
+
+impl InterfaceInfo {
+    /// NDM reports the interface administratively and operationally up.
+    pub fn is_up(&self) -> bool {
+        self.state == "up" && self.link == "up"
+    }
+}
+
+/// One entry of `show ip route` / `show ipv6 route`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct RouteEntry {
+    #[serde(default)]
+    pub destination: String,
+    #[serde(default)]
+    pub gateway: String,
+    #[serde(default)]
+    pub interface: String,
+    #[serde(default, deserialize_with = "lenient_u64")]
+    pub metric: Option<u64>,
+}
+
+impl RouteEntry {
+    pub fn is_default(&self) -> bool {
+        matches!(self.destination.as_str(), "0.0.0.0/0" | "::/0")
+    }
+}
+
+/// NDM reports some numeric fields as strings depending on firmware.
+fn lenient_u64<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+/// Entry of an RCI `status` array.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Status {
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub ident: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RciErrorKind {
+    /// RCI endpoint unreachable or returned a non-JSON/HTTP error response.
+    Transport,
+    /// NDM accepted the request but reported an error status.
+    Rejected,
+}
+
+#[derive(Debug, Clone)]
+pub struct RciError {
+    pub kind: RciErrorKind,
+    pub code: String,
+    pub ident: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for RciError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            RciErrorKind::Transport => write!(f, "rci transport: {}", self.message),
+            RciErrorKind::Rejected => {
+                write!(f, "rci {} [{}] {}", self.ident, self.code, self.message)
+            }
+        }
+    }
+}
+
+impl RciError {
+    fn transport(message: String) -> Self {
+        Self {
+            kind: RciErrorKind::Transport,
+            code: String::new(),
+            ident: String::new(),
+            message,
+        }
+    }
+}
+
+/// Minimal client for the router's local RCI endpoint.
+#[derive(Debug, Clone)]
+pub struct RciClient {
+    url: String,
+}
+
+impl RciClient {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self::with_base_url(&format!("http://{}:{}", host, port))
+    }
+
+    pub fn with_base_url(base_url: &str) -> Self {
+        Self {
+            url: format!("{}/rci/", base_url.trim_end_matches('/')),
+        }
+    }
+
+    /// Send one request. Any `error` entry in a `status` array of the
+    /// response is returned as `RciErrorKind::Rejected`.
+    pub fn execute(&self, request: &Request) -> Result<serde_json::Value, RciError> {
+        let body =
+            serde_json::to_string(request).map_err(|e| RciError::transport(e.to_string()))?;
+        let resp = ureq::post(&self.url)
+            .timeout(RCI_TIMEOUT)
+            .set("Content-Type", "application/json")
+            .send_string(&body)
+            .map_err(|e| RciError::transport(format!("POST {}: {}", self.url, e)))?;
+        let text = resp
+            .into_string()
+            .map_err(|e| RciError::transport(format!("read response: {}", e)))?;
+        let value: serde_json::Value = serde_json::from_str(&text)
+            .map_err(|e| RciError::transport(format!("invalid response: {}", e)))?;
+
+        if let Some(err) = find_error_status(&value) {
+            return Err(RciError {
+                kind: RciErrorKind::Rejected,
+                code: err.code,
+                ident: err.ident,
+                message: err.message,
+            });
+        }
+        Ok(value)
+    }
+
+    pub fn running_config(&self) -> Result<RunningConfig, RciError> {
+        let value = self.execute(&Request::Show(ShowRequest {
+            running_config: Some(Empty {}),
+            ..Default::default()
+        }))?;
+        let body = value
+            .pointer("/show/running-config")
+            .cloned()
+            .unwrap_or_default();
+        serde_json::from_value(body)
+            .map_err(|e| RciError::transport(format!("invalid running-config: {}", e)))
+    }
+
+    pub fn show_interface(&self, name: &str) -> Result<InterfaceInfo, RciError> {
+        let value = self.execute(&Request::Show(ShowRequest {
+            interface: Some(ShowInterface {
+                name: name.to_string(),
+            }),
+            ..Default::default()
+        }))?;
+        let body = value
+            .pointer("/show/interface")
+            .cloned()
+            .unwrap_or_default();
+        serde_json::from_value(body)
+            .map_err(|e| RciError::transport(format!("invalid interface info: {}", e)))
+    }
+
+    pub fn show_routes(&self, ipv6: bool) -> Result<Vec<RouteEntry>, RciError> {
+        let show = ShowIp {
+            route: Some(Empty {}),
+            ..Default::default()
+        };
+        let (request, pointer) = if ipv6 {
+            (
+                ShowRequest {
+                    ipv6: Some(show),
+                    ..Default::default()
+                },
+                "/show/ipv6/route",
+            )
+        } else {
+            (
+                ShowRequest {
+                    ip: Some(show),
+                    ..Default::default()
+                },
+                "/show/ip/route",
+            )
+        };
+        let value = self.execute(&Request::Show(request))?;
+        match value.pointer(pointer) {
+            Some(body @ serde_json::Value::Array(_)) => serde_json::from_value(body.clone())
+                .map_err(|e| RciError::transport(format!("invalid route table: {}", e))),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Addresses of the DNS servers the router's DNS proxy currently uses.
+    pub fn show_name_servers(&self) -> Result<Vec<String>, RciError> {
+        let value = self.execute(&Request::Show(ShowRequest {
+            ip: Some(ShowIp {
+                name_server: Some(Empty {}),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }))?;
+        let servers = match value.pointer("/show/ip/name-server/server") {
+            Some(serde_json::Value::Array(items)) => items.clone(),
+            Some(item @ serde_json::Value::Object(_)) => vec![item.clone()],
+            _ => Vec::new(),
+        };
+        Ok(servers
+            .iter()
+            .filter_map(|s| s.get("address").and_then(|a| a.as_str()))
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// Walk the response and return the first `status` entry marked as error.
+fn find_error_status(value: &serde_json::Value) -> Option<Status> {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(serde_json::Value::Array(items)) = map.get("status") {
+                for item in items {
+                    if let Ok(st) = serde_json::from_value::<Status>(item.clone()) {
+                        if st.status == "error" {
+                            return Some(st);
+                        }
+                    }
+                }
+            }
+            map.values().find_map(find_error_status)
+        }
+        serde_json::Value::Array(items) => items.iter().find_map(find_error_status),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serve one request with `response` and hand back the received body.
+    fn stand_in(response: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut req = server.recv().unwrap();
+            let mut body = String::new();
+            req.as_reader().read_to_string(&mut body).unwrap();
+            let _ = req.respond(tiny_http::Response::from_string(response));
+            body
+        });
+        (base, handle)
+    }
+
+    #[test]
+    fn test_request_serialization() {
+        let req = Request::Ip(IpRequest {
+            route: Some(RouteRequest {
+                default: true,
+                interface: "OpkgTun0".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"ip":{"route":{"default":true,"interface":"OpkgTun0"}}}"#
+        );
+    }
+
+    #[test]
+    fn test_execute_reports_rejected_status() {
+        let (base, handle) = stand_in(
+            r#"{"interface":{"status":[{"status":"error","code":"7405600","ident":"Core::Configurator","message":"unable to find OpkgTun0"}]}}"#,
+        );
+        let client = RciClient::with_base_url(&base);
+        let err = client
+            .execute(&Request::Interface(InterfaceRequest {
+                name: "OpkgTun0".into(),
+                up: Some(true),
+                ..Default::default()
+            }))
+            .unwrap_err();
+        assert_eq!(err.kind, RciErrorKind::Rejected);
+        assert_eq!(err.code, "7405600");
+        assert_eq!(
+            handle.join().unwrap(),
+            r#"{"interface":{"name":"OpkgTun0","up":true}}"#
+        );
+    }
+
+    #[test]
+    fn test_running_config() {
+        let (base, handle) =
+            stand_in(r#"{"show":{"running-config":{"message":["ip route default OpkgTun0"]}}}"#);
+        let config = RciClient::with_base_url(&base).running_config().unwrap();
+        assert_eq!(config.message, vec!["ip route default OpkgTun0"]);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_show_routes() {
+        let (base, handle) = stand_in(
+            r#"{"show":{"ip":{"route":[{"destination":"0.0.0.0/0","gateway":"0.0.0.0","interface":"OpkgTun0","metric":"0"}]}}}"#,
+        );
+        let routes = RciClient::with_base_url(&base).show_routes(false).unwrap();
+        assert_eq!(routes.len(), 1);
+        assert!(routes[0].is_default());
+        assert_eq!(routes[0].interface, "OpkgTun0");
+        assert_eq!(routes[0].metric, Some(0));
+        assert_eq!(handle.join().unwrap(), r#"{"show":{"ip":{"route":{}}}}"#);
+    }
+}
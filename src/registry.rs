@@ -0,0 +1,286 @@
+use crate::config::{
+    AutostartPolicy, InstanceSettings, RoutingSettings, TunnelSettings, WrapperConfig,
+};
+use crate::killswitch::KillSwitch;
+use crate::routelists::RouteLists;
+use crate::routing::InterfaceNames;
+use crate::sessions::StopReason;
+use crate::state::{DesiredState, StateFile};
+use crate::tunnel::TunnelManager;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// ID of the tunnel configured by the top-level `tunnel` block.
+pub const MAIN_TUNNEL_ID: &str = "main";
+
+/// Everything needed to build one `TunnelManager`, resolved and validated
+/// before any of them is created.
+#[derive(Debug, Clone)]
+struct TunnelPlan {
+    id: String,
+    settings: TunnelSettings,
+    instance: InstanceSettings,
+    journal_path: String,
+    default_route: bool,
+}
+
+/// All tunnels run by this daemon, in config order with `main` first.
+pub struct TunnelRegistry {
+    tunnels: Vec<(String, Arc<TunnelManager>)>,
+    desired: StateFile,
+}
+
+impl TunnelRegistry {
+    pub fn new(cfg: &WrapperConfig, route_lists: Arc<RouteLists>) -> Result<Self, String> {
+        let tunnels = plan(cfg)?
+            .into_iter()
+            .map(|p| {
+                let mut routing = RoutingSettings {
+                    journal_path: p.journal_path,
+                    ..cfg.routing.clone()
+                };
+                if p.id != MAIN_TUNNEL_ID && !routing.quality.persist_path.is_empty() {
+                    routing.quality.persist_path =
+                        derived_path(&routing.quality.persist_path, &p.id);
+                }
+                if p.id != MAIN_TUNNEL_ID && !routing.sessions.path.is_empty() {
+                    routing.sessions.path = derived_path(&routing.sessions.path, &p.id);
+                }
+                if p.id != MAIN_TUNNEL_ID && !routing.quota.persist_path.is_empty() {
+                    routing.quota.persist_path = derived_path(&routing.quota.persist_path, &p.id);
+                }
+                // The kill switch guards LAN traffic, which follows the default route.
+                let killswitch = (p.default_route && cfg.killswitch.enabled).then(|| {
+                    KillSwitch::new(
+                        &cfg.killswitch,
+                        &InterfaceNames::from_settings(&p.instance).opkg_tun,
+                    )
+                });
+                let dns = (p.default_route && cfg.dns.is_ndm()).then(|| cfg.dns.clone());
+                let manager = TunnelManager::new(
+                    p.settings,
+                    &routing,
+                    &p.instance,
+                    p.default_route,
+                    killswitch,
+                    dns,
+                    route_lists.for_tunnel(&p.id),
+                );
+                (p.id, manager)
+            })
+            .collect();
+        Ok(Self {
+            tunnels,
+            desired: StateFile::load(&cfg.startup.state_path),
+        })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Arc<TunnelManager>> {
+        self.tunnels.iter().find(|(i, _)| i == id).map(|(_, t)| t)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<TunnelManager>)> {
+        self.tunnels.iter().map(|(id, t)| (id.as_str(), t))
+    }
+
+    /// Remember the state requested for `id` for the next start.
+    pub fn set_desired(&self, id: &str, state: DesiredState) {
+        self.desired.set(id, state);
+    }
+
+    /// Bring `id` into its startup state: connected, left down, or paused
+    /// for the rest of a pause recorded before the restart.
+    pub fn autostart(&self, id: &str, policy: AutostartPolicy) -> Result<(), String> {
+        let Some(tunnel) = self.get(id) else {
+            return Err(format!("unknown tunnel '{}'", id));
+        };
+        let desired = match policy {
+            AutostartPolicy::Always => DesiredState::Connected,
+            AutostartPolicy::Never => DesiredState::Disconnected,
+            AutostartPolicy::Restore => self.desired.get(id).unwrap_or(DesiredState::Connected),
+        };
+        let now = crate::util::unix_now();
+        match desired {
+            DesiredState::Connected => tunnel.start(),
+            DesiredState::Paused { until } if until > now => {
+                tunnel.restore_pause(until - now);
+                Ok(())
+            }
+            DesiredState::Paused { .. } => tunnel.start(),
+            DesiredState::Disconnected => {
+                log::info!("[tunnels] '{}' stays disconnected", id);
+                // An adopted client of a previous run is stopped too.
+                if tunnel.get_status().connected {
+                    tunnel.stop(StopReason::User);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Stop every tunnel with a full teardown.
+    pub fn stop_all(&self) {
+        for (id, tunnel) in self.iter() {
+            log::info!("[tunnels] stopping '{}'", id);
+            tunnel.stop(StopReason::Shutdown);
+        }
+    }
+}
+
+/// `<base>` with `-<id>` inserted before the extension.
+fn derived_path(base: &str, id: &str) -> String {
+    let path = std::path::Path::new(base);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => path
+            .with_file_name(format!(
+                "{}-{}.{}",
+                stem.to_string_lossy(),
+                id,
+                ext.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .into_owned(),
+        _ => format!("{}-{}", base, id),
+    }
+}
+
+fn plan(cfg: &WrapperConfig) -> Result<Vec<TunnelPlan>, String> {
+    let owner = cfg.routing.default_route_owner.trim();
+    let mut plans = vec![TunnelPlan {
+        id: MAIN_TUNNEL_ID.to_string(),
+        settings: cfg.tunnel.clone(),
+        instance: cfg.instance.clone(),
+        journal_path: cfg.routing.journal_path.clone(),
+        default_route: owner == MAIN_TUNNEL_ID,
+    }];
+
+    for (pos, extra) in cfg.tunnels.iter().enumerate() {
+        let id = extra.id.trim();
+        let valid_id = !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_id {
+            return Err(format!(
+                "tunnels[{}]: id '{}' must be non-empty [A-Za-z0-9_-]",
+                pos, extra.id
+            ));
+        }
+        let mut instance = extra.instance.clone();
+        if instance.index == 0 {
+            instance.index = pos as u32 + 1;
+        }
+        let journal_path = if extra.journal_path.trim().is_empty() {
+            derived_path(&cfg.routing.journal_path, id)
+        } else {
+            extra.journal_path.trim().to_string()
+        };
+        plans.push(TunnelPlan {
+            id: id.to_string(),
+            settings: extra.tunnel.clone(),
+            instance,
+            journal_path,
+            default_route: owner == id,
+        });
+    }
+
+    if !owner.is_empty() && !plans.iter().any(|p| p.default_route) {
+        return Err(format!(
+            "routing.default_route_owner '{}' is not a configured tunnel",
+            owner
+        ));
+    }
+
+    let mut ids = HashSet::new();
+    let mut taken = HashSet::new();
+    for p in &plans {
+        if !ids.insert(p.id.as_str()) {
+            return Err(format!("duplicate tunnel id '{}'", p.id));
+        }
+        let names = InterfaceNames::from_settings(&p.instance);
+        for name in [
+            names.tun,
+            names.opkg_tun,
+            names.opkg_backup,
+            names.ndm,
+            p.journal_path.clone(),
+        ] {
+            if !taken.insert(name.clone()) {
+                return Err(format!("tunnel '{}': '{}' is already used", p.id, name));
+            }
+        }
+    }
+    for source in &cfg.route_lists.sources {
+        if !ids.contains(source.tunnel.trim()) {
+            return Err(format!(
+                "route list '{}': tunnel '{}' is not configured",
+                source.name, source.tunnel
+            ));
+        }
+    }
+    Ok(plans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExtraTunnel;
+
+    fn extra(id: &str) -> ExtraTunnel {
+        ExtraTunnel {
+            id: id.into(),
+            tunnel: TunnelSettings::default(),
+            instance: InstanceSettings::default(),
+            journal_path: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_plan_derives_names_and_owner() {
+        let mut cfg = WrapperConfig {
+            tunnels: vec![extra("work"), extra("media")],
+            ..Default::default()
+        };
+        cfg.routing.default_route_owner = "media".into();
+        let plans = plan(&cfg).unwrap();
+        assert_eq!(
+            plans.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(),
+            vec!["main", "work", "media"]
+        );
+        assert_eq!(plans[1].instance.index, 1);
+        assert_eq!(plans[2].instance.index, 2);
+        assert_eq!(
+            plans[1].journal_path,
+            "/opt/var/lib/trusttunnel/routing-journal-work.json"
+        );
+        assert_eq!(
+            plans.iter().filter(|p| p.default_route).count(),
+            1,
+            "exactly one default route owner"
+        );
+        assert!(plans[2].default_route);
+    }
+
+    #[test]
+    fn test_plan_rejects_collisions() {
+        let mut cfg = WrapperConfig {
+            tunnels: vec![extra("main")],
+            ..Default::default()
+        };
+        assert!(plan(&cfg).unwrap_err().contains("duplicate"));
+
+        let mut clash = extra("work");
+        clash.instance.ndm_name = "OpkgTun0".into();
+        cfg.tunnels = vec![clash];
+        assert!(plan(&cfg).unwrap_err().contains("OpkgTun0"));
+
+        cfg.tunnels = vec![extra("work")];
+        cfg.routing.default_route_owner = "nope".into();
+        assert!(plan(&cfg).is_err());
+
+        cfg.routing.default_route_owner.clear();
+        cfg.route_lists.sources =
+            serde_json::from_str(r#"[{"name": "office", "tunnel": "media"}]"#).unwrap();
+        assert!(plan(&cfg).unwrap_err().contains("media"));
+    }
+}
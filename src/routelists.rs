@@ -0,0 +1,519 @@
+use crate::config::{RouteListSettings, RouteListSource, TunnelSettings};
+use crate::logs;
+use crate::util::{atomic_write, unix_now};
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_LIST_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Snapshot of a route list source for the API.
+#[derive(Debug, Clone, Default)]
+pub struct SourceStatus {
+    pub name: String,
+    pub kind: String,
+    pub location: String,
+    pub target: String,
+    pub tunnel: String,
+    pub enabled: bool,
+    pub prefixes: usize,
+    pub last_fetch: Option<u64>,
+    pub last_status: String,
+    pub last_error: String,
+}
+
+struct SourceState {
+    source: RouteListSource,
+    prefixes: Vec<String>,
+    etag: String,
+    last_fetch: Option<u64>,
+    last_status: String,
+    last_error: String,
+    /// Bumped whenever a refresh changes `prefixes`.
+    generation: u64,
+}
+
+enum FetchOutcome {
+    Updated { prefixes: Vec<String>, etag: String },
+    NotModified,
+}
+
+/// Keeps route lists loaded from files or URLs and merges them into tunnel routes.
+pub struct RouteLists {
+    cache_dir: PathBuf,
+    refresh_interval: Duration,
+    sources: Mutex<Vec<SourceState>>,
+}
+
+/// The route lists of one tunnel, as handed to its `TunnelManager`.
+pub struct TunnelRouteLists {
+    lists: Arc<RouteLists>,
+    tunnel: String,
+}
+
+impl TunnelRouteLists {
+    /// Changes whenever this tunnel's merged prefixes may have changed;
+    /// compare with the value read before the last `apply`.
+    pub fn generation(&self) -> u64 {
+        self.lists.generation(&self.tunnel)
+    }
+
+    pub fn apply(&self, settings: &mut TunnelSettings) {
+        self.lists.apply(&self.tunnel, settings);
+    }
+}
+
+impl RouteLists {
+    pub fn new(settings: &RouteListSettings) -> Arc<Self> {
+        let sources = settings
+            .sources
+            .iter()
+            .map(|source| SourceState {
+                source: source.clone(),
+                prefixes: Vec::new(),
+                etag: String::new(),
+                last_fetch: None,
+                last_status: "pending".into(),
+                last_error: String::new(),
+                generation: 0,
+            })
+            .collect();
+        let lists = Self {
+            cache_dir: PathBuf::from(&settings.cache_dir),
+            refresh_interval: Duration::from_secs(settings.refresh_interval.max(60)),
+            sources: Mutex::new(sources),
+        };
+        lists.load_cache();
+        Arc::new(lists)
+    }
+
+    /// Seed URL sources from the on-disk cache so the first connect does not
+    /// have to wait for the network.
+    fn load_cache(&self) {
+        let mut sources = self.sources.lock().unwrap();
+        for state in sources.iter_mut() {
+            if state.source.url.is_empty() {
+                continue;
+            }
+            let (list_path, etag_path) = self.cache_paths(&state.source.name);
+            if let Ok(body) = std::fs::read_to_string(&list_path) {
+                let (prefixes, _) = parse_list(&body);
+                state.prefixes = aggregate(&prefixes);
+                state.etag = std::fs::read_to_string(&etag_path)
+                    .map(|s| s.trim().to_string())
+                    .unwrap_or_default();
+                state.last_status = "cached".into();
+            }
+        }
+    }
+
+    fn cache_paths(&self, name: &str) -> (PathBuf, PathBuf) {
+        let safe: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        (
+            self.cache_dir.join(format!("{}.list", safe)),
+            self.cache_dir.join(format!("{}.etag", safe)),
+        )
+    }
+
+    /// Re-read every enabled source. Failures keep the previous prefixes.
+    pub fn refresh_all(&self) {
+        let snapshot: Vec<(RouteListSource, String)> = self
+            .sources
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| (s.source.clone(), s.etag.clone()))
+            .collect();
+
+        for (idx, (source, etag)) in snapshot.into_iter().enumerate() {
+            if !source.enabled {
+                continue;
+            }
+            let result = self.fetch_source(&source, &etag);
+            let now = unix_now();
+
+            let mut sources = self.sources.lock().unwrap();
+            let Some(state) = sources.get_mut(idx) else {
+                break;
+            };
+            state.last_fetch = Some(now);
+            let msg = match result {
+                Ok(FetchOutcome::Updated { prefixes, etag }) => {
+                    if state.prefixes != prefixes {
+                        state.generation += 1;
+                    }
+                    state.prefixes = prefixes;
+                    state.etag = etag;
+                    state.last_status = "ok".into();
+                    state.last_error.clear();
+                    format!(
+                        "[routes] {}: {} prefixes loaded",
+                        source.name,
+                        state.prefixes.len()
+                    )
+                }
+                Ok(FetchOutcome::NotModified) => {
+                    state.last_status = "not_modified".into();
+                    state.last_error.clear();
+                    format!("[routes] {}: not modified", source.name)
+                }
+                Err(e) => {
+                    state.last_status = "error".into();
+                    state.last_error = e.clone();
+                    format!("[routes] {}: refresh failed: {}", source.name, e)
+                }
+            };
+            drop(sources);
+            log::info!("{}", msg);
+            logs::global_buffer().push(msg);
+        }
+    }
+
+    fn fetch_source(&self, source: &RouteListSource, etag: &str) -> Result<FetchOutcome, String> {
+        if !source.file.is_empty() {
+            let body = std::fs::read_to_string(&source.file)
+                .map_err(|e| format!("read {}: {}", source.file, e))?;
+            return Ok(updated_from_body(&body, String::new()));
+        }
+        if source.url.is_empty() {
+            return Err("neither file nor url is set".into());
+        }
+
+        let mut req = ureq::get(&source.url).timeout(FETCH_TIMEOUT);
+        if !etag.is_empty() {
+            req = req.set("If-None-Match", etag);
+        }
+        let resp = req
+            .call()
+            .map_err(|e| format!("GET {}: {}", source.url, e))?;
+        if resp.status() == 304 {
+            return Ok(FetchOutcome::NotModified);
+        }
+        let new_etag = resp.header("ETag").unwrap_or("").to_string();
+
+        let mut body = String::new();
+        resp.into_reader()
+            .take(MAX_LIST_BYTES + 1)
+            .read_to_string(&mut body)
+            .map_err(|e| format!("read body: {}", e))?;
+        if body.len() as u64 > MAX_LIST_BYTES {
+            return Err(format!("list exceeds {} bytes", MAX_LIST_BYTES));
+        }
+
+        let (list_path, etag_path) = self.cache_paths(&source.name);
+        let cached =
+            atomic_write(&list_path, &body).and_then(|_| atomic_write(&etag_path, &new_etag));
+        if let Err(e) = cached {
+            log::warn!("[routes] {}: failed to write cache: {}", source.name, e);
+        }
+
+        Ok(updated_from_body(&body, new_etag))
+    }
+
+    /// Periodic refresh -- call from a dedicated thread.
+    pub fn refresh_loop(self: &Arc<Self>) {
+        if self.sources.lock().unwrap().is_empty() {
+            return;
+        }
+        loop {
+            self.refresh_all();
+            std::thread::sleep(self.refresh_interval);
+        }
+    }
+
+    /// The lists that extend the routes of `tunnel`.
+    pub fn for_tunnel(self: &Arc<Self>, tunnel: &str) -> TunnelRouteLists {
+        TunnelRouteLists {
+            lists: self.clone(),
+            tunnel: tunnel.to_string(),
+        }
+    }
+
+    /// Sum of the refresh generations of `tunnel`'s sources, so a change to
+    /// another tunnel's list leaves it alone.
+    fn generation(&self, tunnel: &str) -> u64 {
+        self.sources
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.source.tunnel.trim() == tunnel)
+            .map(|s| s.generation)
+            .sum()
+    }
+
+    pub fn statuses(&self) -> Vec<SourceStatus> {
+        self.sources
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| SourceStatus {
+                name: s.source.name.clone(),
+                kind: if s.source.file.is_empty() {
+                    "url"
+                } else {
+                    "file"
+                }
+                .into(),
+                location: if s.source.file.is_empty() {
+                    s.source.url.clone()
+                } else {
+                    s.source.file.clone()
+                },
+                target: s.source.target.clone(),
+                tunnel: s.source.tunnel.clone(),
+                enabled: s.source.enabled,
+                prefixes: s.prefixes.len(),
+                last_fetch: s.last_fetch,
+                last_status: s.last_status.clone(),
+                last_error: s.last_error.clone(),
+            })
+            .collect()
+    }
+
+    /// Merge the prefixes of `tunnel`'s lists into its included/excluded routes.
+    fn apply(&self, tunnel: &str, settings: &mut TunnelSettings) {
+        let mut included = Vec::new();
+        let mut excluded = Vec::new();
+        for state in self.sources.lock().unwrap().iter() {
+            if !state.source.enabled || state.source.tunnel.trim() != tunnel {
+                continue;
+            }
+            match state.source.target.as_str() {
+                "excluded" => excluded.extend(state.prefixes.iter().cloned()),
+                _ => included.extend(state.prefixes.iter().cloned()),
+            }
+        }
+
+        // An empty list in general mode already means "everything", so adding
+        // prefixes to it would narrow the tunnel instead of extending it.
+        let general_all = settings.included_routes.is_empty() && settings.vpn_mode == "general";
+        if !included.is_empty() && !general_all {
+            included.extend(settings.included_routes.iter().cloned());
+            settings.included_routes = aggregate(&included);
+        }
+        if !excluded.is_empty() {
+            excluded.extend(settings.excluded_routes.iter().cloned());
+            settings.excluded_routes = aggregate(&excluded);
+        }
+    }
+}
+
+fn updated_from_body(body: &str, etag: String) -> FetchOutcome {
+    let (prefixes, invalid) = parse_list(body);
+    if invalid > 0 {
+        log::debug!("[routes] skipped {} invalid lines", invalid);
+    }
+    FetchOutcome::Updated {
+        prefixes: aggregate(&prefixes),
+        etag,
+    }
+}
+
+/// Split a list body into prefixes, ignoring blank lines and `#` comments.
+/// Returns the candidate entries and the number of lines that failed to parse.
+fn parse_list(body: &str) -> (Vec<String>, usize) {
+    let mut out = Vec::new();
+    let mut invalid = 0usize;
+    for line in body.lines() {
+        let entry = line.split('#').next().unwrap_or("").trim();
+        if entry.is_empty() {
+            continue;
+        }
+        if Cidr::parse(entry).is_some() {
+            out.push(entry.to_string());
+        } else {
+            invalid += 1;
+        }
+    }
+    (out, invalid)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Cidr {
+    v6: bool,
+    addr: u128,
+    len: u8,
+}
+
+impl Cidr {
+    fn parse(raw: &str) -> Option<Self> {
+        let (ip_part, len_part) = match raw.split_once('/') {
+            Some((ip, len)) => (ip, Some(len)),
+            None => (raw, None),
+        };
+        let (v6, addr) = match ip_part.trim().parse::<IpAddr>().ok()? {
+            IpAddr::V4(v4) => (false, u32::from(v4) as u128),
+            IpAddr::V6(v6) => (true, u128::from(v6)),
+        };
+        let max = if v6 { 128 } else { 32 };
+        let len = match len_part {
+            Some(l) => l.trim().parse::<u8>().ok()?,
+            None => max,
+        };
+        if len > max {
+            return None;
+        }
+        Some(Self {
+            v6,
+            addr: addr & mask(len, max),
+            len,
+        })
+    }
+
+    fn max_len(&self) -> u8 {
+        if self.v6 {
+            128
+        } else {
+            32
+        }
+    }
+
+    fn contains(&self, other: &Cidr) -> bool {
+        self.v6 == other.v6
+            && self.len <= other.len
+            && other.addr & mask(self.len, self.max_len()) == self.addr
+    }
+
+    /// If `self` and `next` are the two halves of one prefix, return it.
+    fn merge_sibling(&self, next: &Cidr) -> Option<Cidr> {
+        if self.v6 != next.v6 || self.len != next.len || self.len == 0 {
+            return None;
+        }
+        let bit = 1u128 << (self.max_len() - self.len);
+        if self.addr & bit == 0 && self.addr | bit == next.addr {
+            Some(Cidr {
+                v6: self.v6,
+                addr: self.addr,
+                len: self.len - 1,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.v6 {
+            write!(f, "{}/{}", Ipv6Addr::from(self.addr), self.len)
+        } else {
+            write!(f, "{}/{}", Ipv4Addr::from(self.addr as u32), self.len)
+        }
+    }
+}
+
+fn mask(len: u8, max: u8) -> u128 {
+    if len == 0 {
+        0
+    } else {
+        (!0u128 >> (128 - len)) << (max - len)
+    }
+}
+
+/// Deduplicate prefixes, drop ones covered by a shorter prefix and merge
+/// adjacent siblings into their parent. Invalid entries are dropped.
+pub fn aggregate(prefixes: &[String]) -> Vec<String> {
+    let mut cidrs: Vec<Cidr> = prefixes.iter().filter_map(|p| Cidr::parse(p)).collect();
+    cidrs.sort();
+    cidrs.dedup();
+
+    let mut merged: Vec<Cidr> = Vec::with_capacity(cidrs.len());
+    for cidr in cidrs {
+        if merged.last().is_some_and(|last| last.contains(&cidr)) {
+            continue;
+        }
+        merged.push(cidr);
+        while merged.len() >= 2 {
+            let n = merged.len();
+            match merged[n - 2].merge_sibling(&merged[n - 1]) {
+                Some(parent) => {
+                    merged.truncate(n - 2);
+                    merged.push(parent);
+                }
+                None => break,
+            }
+        }
+    }
+
+    merged.iter().map(|c| c.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_aggregate_merges_and_dedups() {
+        let input = strings(&[
+            "10.0.0.0/25",
+            "10.0.0.128/25",
+            "10.0.0.5",
+            "10.0.1.0/24",
+            "192.168.1.7/24",
+            "192.168.1.0/24",
+            "2001:db8::/33",
+            "2001:db8:8000::/33",
+            "garbage",
+        ]);
+        assert_eq!(
+            aggregate(&input),
+            strings(&["10.0.0.0/23", "192.168.1.0/24", "2001:db8::/32"])
+        );
+    }
+
+    #[test]
+    fn test_lists_apply_to_their_tunnel_only() {
+        let dir = std::env::temp_dir().join(format!("tt-routelists-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("work.txt");
+        std::fs::write(&file, "10.1.0.0/16\n").unwrap();
+        let lists = RouteLists::new(&RouteListSettings {
+            sources: vec![RouteListSource {
+                name: "work".into(),
+                file: file.to_str().unwrap().into(),
+                url: String::new(),
+                target: "included".into(),
+                tunnel: "work".into(),
+                enabled: true,
+            }],
+            ..Default::default()
+        });
+        let (main, work) = (lists.for_tunnel("main"), lists.for_tunnel("work"));
+        lists.refresh_all();
+        assert_eq!((main.generation(), work.generation()), (0, 1));
+
+        let mut settings = TunnelSettings {
+            vpn_mode: "selective".into(),
+            included_routes: Vec::new(),
+            ..Default::default()
+        };
+        main.apply(&mut settings);
+        assert!(settings.included_routes.is_empty());
+        work.apply(&mut settings);
+        assert_eq!(settings.included_routes, strings(&["10.1.0.0/16"]));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_parse_list_skips_comments() {
+        let (prefixes, invalid) = parse_list("# header\n1.2.3.0/24 # office\n\nnot-an-ip\n::1\n");
+        assert_eq!(prefixes, strings(&["1.2.3.0/24", "::1"]));
+        assert_eq!(invalid, 1);
+    }
+}
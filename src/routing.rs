@@ -1,22 +1,62 @@
+use crate::config::InstanceSettings;
+use crate::journal::{Change, Journal};
+use crate::ndm::{self, NameServer, NdmCommand};
+use crate::rci::{InterfaceInfo, RouteEntry};
 use std::process::Command;
-use std::sync::{Mutex, OnceLock};
+use std::sync::Mutex;
 use std::time::Duration;
 use std::{
     io::ErrorKind,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
 };
 
-const TUN_NAME: &str = "tun0";
-const OPKG_TUN_NAME: &str = "opkgtun0";
-const OPKG_TUN_BACKUP_NAME: &str = "opkgbak0";
-const NDM_IF_NAME: &str = "OpkgTun0";
 const TUN_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
 const TUN_POLL_INTERVAL: Duration = Duration::from_millis(500);
 const TUN_RENAME_RETRY_TIMEOUT: Duration = Duration::from_secs(3);
 const TUN_RENAME_RETRY_POLL_INTERVAL: Duration = Duration::from_millis(100);
 const NDM_VERIFY_TIMEOUT: Duration = Duration::from_secs(5);
-const NDM_RETRY_BASE_DELAY_MS: u64 = 200;
-const NDM_MAX_ATTEMPTS: u32 = 10;
+
+/// Linux and NDM interface names of one wrapper instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceNames {
+    /// Device the client creates.
+    pub tun: String,
+    /// Name `tun` is renamed to so NDM binds it to `ndm`.
+    pub opkg_tun: String,
+    /// Temporary name while replacing a stale `opkg_tun`.
+    pub opkg_backup: String,
+    pub ndm: String,
+    /// Written to the NDM interface description to mark it as ours.
+    pub owner: String,
+}
+
+impl InterfaceNames {
+    pub fn for_index(index: u32) -> Self {
+        Self {
+            tun: format!("tun{}", index),
+            opkg_tun: format!("opkgtun{}", index),
+            opkg_backup: format!("opkgbak{}", index),
+            ndm: format!("OpkgTun{}", index),
+            owner: format!("trusttunnel-keenetic-{}", index),
+        }
+    }
+
+    /// Names derived from `instance.index`, with explicit overrides applied.
+    pub fn from_settings(instance: &InstanceSettings) -> Self {
+        let mut names = Self::for_index(instance.index);
+        for (configured, slot) in [
+            (&instance.tun_name, &mut names.tun),
+            (&instance.opkg_tun_name, &mut names.opkg_tun),
+            (&instance.opkg_backup_name, &mut names.opkg_backup),
+            (&instance.ndm_name, &mut names.ndm),
+        ] {
+            if !configured.trim().is_empty() {
+                *slot = configured.trim().to_string();
+            }
+        }
+        names
+    }
+}
 
 fn run_cmd(program: &str, args: &[&str]) -> Result<String, String> {
     let output = Command::new(program)
@@ -52,7 +92,10 @@ pub fn current_wan_interface() -> Option<String> {
                     || dev.starts_with("lan")
                     || dev.starts_with("vlan")
                     || dev.starts_with("wl");
-                if dev != OPKG_TUN_NAME && dev != TUN_NAME && !is_lan_like {
+                let is_tunnel = dev.starts_with("tun")
+                    || dev.starts_with("opkgtun")
+                    || dev.starts_with("opkgbak");
+                if !is_tunnel && !is_lan_like {
                     return Some(dev.to_string());
                 }
             }
@@ -61,6 +104,14 @@ pub fn current_wan_interface() -> Option<String> {
     None
 }
 
+/// Output device the kernel picks for `ip` (`ip route get`).
+pub fn route_dev(ip: IpAddr) -> Option<String> {
+    let out = run_cmd("ip", &["route", "get", &ip.to_string()]).ok()?;
+    let parts: Vec<&str> = out.split_whitespace().collect();
+    let i = parts.iter().position(|&p| p == "dev")?;
+    parts.get(i + 1).map(|d| d.to_string())
+}
+
 fn parse_endpoint_ip(raw: &str) -> Option<IpAddr> {
     if let Ok(sock) = raw.parse::<SocketAddr>() {
         return Some(sock.ip());
@@ -76,11 +127,90 @@ fn parse_endpoint_ip(raw: &str) -> Option<IpAddr> {
     None
 }
 
-fn extract_server_ips(addresses: &[String]) -> Vec<IpAddr> {
-    addresses
-        .iter()
-        .filter_map(|addr| parse_endpoint_ip(addr))
-        .collect()
+/// Host part of an `addresses` entry that is not an IP literal
+/// (`vpn.example.com:443` or bare `vpn.example.com`).
+fn endpoint_host(raw: &str) -> &str {
+    match raw.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => raw,
+    }
+}
+
+/// True if any entry needs DNS to find the server IP.
+pub fn has_hostnames(addresses: &[String]) -> bool {
+    addresses.iter().any(|a| parse_endpoint_ip(a).is_none())
+}
+
+/// Resolve endpoint addresses to server IPs. IP literals are used as-is,
+/// hostnames go through the system resolver. Returns the unique IPs and one
+/// error per hostname that could not be resolved.
+pub fn resolve_server_ips(addresses: &[String]) -> (Vec<IpAddr>, Vec<String>) {
+    resolve_server_ips_with(addresses, |host| {
+        (host, 0u16)
+            .to_socket_addrs()
+            .map(|resolved| resolved.map(|addr| addr.ip()).collect())
+            .map_err(|e| e.to_string())
+    })
+}
+
+/// Like `resolve_server_ips`, but hostnames are queried directly from
+/// `servers` out of `dev`, so the answer does not depend on the tunnel or
+/// the router's DNS proxy. Servers are tried in order, each limited by
+/// `timeout`.
+pub fn resolve_server_ips_via(
+    addresses: &[String],
+    servers: &[String],
+    dev: &str,
+    timeout: Duration,
+) -> (Vec<IpAddr>, Vec<String>) {
+    resolve_server_ips_with(addresses, |host| {
+        let mut errors = Vec::new();
+        for server in servers {
+            let answer = server
+                .parse::<SocketAddr>()
+                .map_err(|e| format!("server '{}': {}", server, e))
+                .and_then(|server| crate::dns::resolve_host(server, host, timeout, Some(dev)));
+            match answer {
+                Ok(ips) if !ips.is_empty() => return Ok(ips),
+                Ok(_) => errors.push(format!("{}: no addresses", server)),
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.is_empty() {
+            Err("no resolve_servers configured".into())
+        } else {
+            Err(errors.join(", "))
+        }
+    })
+}
+
+fn resolve_server_ips_with<F>(addresses: &[String], lookup: F) -> (Vec<IpAddr>, Vec<String>)
+where
+    F: Fn(&str) -> Result<Vec<IpAddr>, String>,
+{
+    let mut ips: Vec<IpAddr> = Vec::new();
+    let mut errors = Vec::new();
+    let mut push = |ip: IpAddr| {
+        if !ips.contains(&ip) {
+            ips.push(ip);
+        }
+    };
+
+    for raw in addresses {
+        if let Some(ip) = parse_endpoint_ip(raw) {
+            push(ip);
+            continue;
+        }
+        let host = endpoint_host(raw);
+        match lookup(host) {
+            Ok(resolved) if resolved.is_empty() => {
+                errors.push(format!("{}: no addresses", host));
+            }
+            Ok(resolved) => resolved.into_iter().for_each(&mut push),
+            Err(e) => errors.push(format!("{}: {}", host, e)),
+        }
+    }
+    (ips, errors)
 }
 
 fn delete_server_host_route(ip: IpAddr) {
@@ -90,6 +220,17 @@ fn delete_server_host_route(ip: IpAddr) {
     }
 }
 
+fn delete_server_host_route_via(ip: IpAddr, dev: &str) -> Result<(), String> {
+    match ip {
+        IpAddr::V4(v4) => run_cmd("ip", &["route", "del", &format!("{}/32", v4), "dev", dev]),
+        IpAddr::V6(v6) => run_cmd(
+            "ip",
+            &["-6", "route", "del", &format!("{}/128", v6), "dev", dev],
+        ),
+    }
+    .map(|_| ())
+}
+
 fn add_server_host_route(ip: IpAddr, wan_if: &str) -> Result<(), String> {
     match ip {
         IpAddr::V4(v4) => run_cmd(
@@ -105,104 +246,7 @@ fn add_server_host_route(ip: IpAddr, wan_if: &str) -> Result<(), String> {
     }
 }
 
-fn find_ndmc() -> &'static str {
-    static PATHS: &[&str] = &["/usr/bin/ndmc", "/bin/ndmc", "/sbin/ndmc"];
-    for p in PATHS {
-        if std::path::Path::new(p).exists() {
-            return p;
-        }
-    }
-    "ndmc"
-}
-
-fn ndmc_lock() -> &'static Mutex<()> {
-    static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
-    LOCK.get_or_init(|| Mutex::new(()))
-}
-
-fn is_ndm_transient_error(message: &str) -> bool {
-    let m = message.to_ascii_lowercase();
-    m.contains("0xcffd0060")
-        || m.contains("0xcffd009f")
-        || m.contains("failed to initialize")
-        || m.contains("unable to find opkgtun0")
-        || m.contains("temporarily unavailable")
-}
-
-fn ndmc_exec_once(cmd: &str) -> Result<String, String> {
-    let bin = find_ndmc();
-    let output = Command::new(bin)
-        .args(["-c", cmd])
-        .output()
-        .map_err(|e| format!("exec '{}' error: {}", cmd, e))?;
-    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-    let combined = match (stdout.is_empty(), stderr.is_empty()) {
-        (false, false) => format!("{} | {}", stdout, stderr),
-        (false, true) => stdout,
-        (true, false) => stderr,
-        (true, true) => String::new(),
-    };
-    if output.status.success() {
-        Ok(combined)
-    } else {
-        Err(format!(
-            "'{}' exit={} {}",
-            cmd,
-            output.status.code().unwrap_or(-1),
-            combined
-        ))
-    }
-}
-
-fn summarize_ndmc_output(_cmd: &str, output: &str) -> String {
-    let trimmed = output.trim();
-    const MAX_LEN: usize = 240;
-    if trimmed.len() > MAX_LEN {
-        format!("{}...", &trimmed[..MAX_LEN])
-    } else {
-        trimmed.to_string()
-    }
-}
-
-fn ndmc(cmd: &str) -> Result<String, String> {
-    let _guard = ndmc_lock().lock().unwrap();
-    let mut last_err = String::new();
-    for attempt in 1..=NDM_MAX_ATTEMPTS {
-        match ndmc_exec_once(cmd) {
-            Ok(output) => {
-                let summary = summarize_ndmc_output(cmd, &output);
-                let msg = if summary.is_empty() {
-                    format!("[ndmc] ok: {}", cmd)
-                } else {
-                    format!("[ndmc] ok: {} ({})", cmd, summary)
-                };
-                log::info!("{}", msg);
-                crate::logs::global_buffer().push(msg);
-                return Ok(output);
-            }
-            Err(err) => {
-                last_err = err;
-                let transient = is_ndm_transient_error(&last_err);
-                let msg = format!(
-                    "[ndmc] attempt {}/{} failed: {}",
-                    attempt, NDM_MAX_ATTEMPTS, last_err
-                );
-                log::warn!("{}", msg);
-                crate::logs::global_buffer().push(msg);
-                if transient && attempt < NDM_MAX_ATTEMPTS {
-                    let backoff = (NDM_RETRY_BASE_DELAY_MS * attempt as u64).min(1000);
-                    std::thread::sleep(Duration::from_millis(backoff));
-                    continue;
-                }
-                return Err(last_err);
-            }
-        }
-    }
-    Err(last_err)
-}
-
-fn verify_kernel_default_route_via_opkgtun() -> bool {
+fn verify_kernel_default_route_via_opkgtun(names: &InterfaceNames) -> bool {
     let start = std::time::Instant::now();
     while start.elapsed() < NDM_VERIFY_TIMEOUT {
         if let Ok(out) = run_cmd("ip", &["-o", "route", "show", "default"]) {
@@ -210,7 +254,7 @@ fn verify_kernel_default_route_via_opkgtun() -> bool {
                 line.split_whitespace()
                     .collect::<Vec<_>>()
                     .windows(2)
-                    .any(|w| w == ["dev", OPKG_TUN_NAME])
+                    .any(|w| w == ["dev", &names.opkg_tun])
             }) {
                 return true;
             }
@@ -220,20 +264,71 @@ fn verify_kernel_default_route_via_opkgtun() -> bool {
     false
 }
 
-fn ndmc_soft(cmd: &str) {
-    if let Err(e) = ndmc(cmd) {
-        let msg = format!("[ndmc] soft-fail: {}", e);
-        log::warn!("{}", msg);
-        crate::logs::global_buffer().push(msg);
+fn ndm_soft(cmd: &NdmCommand) -> bool {
+    match ndm::run(cmd) {
+        Ok(_) => true,
+        Err(e) => {
+            let msg = format!("[ndm] soft-fail: {}", e);
+            log::warn!("{}", msg);
+            crate::logs::global_buffer().push(msg);
+            false
+        }
     }
 }
 
-fn ndmc_required(cmd: &str) -> Result<(), String> {
-    ndmc(cmd).map(|_| ())
+fn ndm_required(cmd: &NdmCommand) -> Result<(), String> {
+    ndm::run(cmd).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Run an NDM command and record it in the journal together with its inverse.
+pub fn ndm_journaled(
+    cmd: NdmCommand,
+    undo: NdmCommand,
+    journal: &Mutex<Journal>,
+) -> Result<(), String> {
+    ndm_required(&cmd)?;
+    journal
+        .lock()
+        .unwrap()
+        .record(Change::NdmCommand { command: cmd, undo });
+    Ok(())
+}
+
+/// Execute the inverse of a journaled change.
+pub fn undo_change(change: &Change) -> Result<(), String> {
+    match change {
+        Change::NdmInterface { name } => {
+            ndm_required(&NdmCommand::NoInterface { name: name.clone() })
+        }
+        Change::NdmCommand { undo, .. } => ndm_required(undo),
+        Change::LinkRename { from, to } => {
+            run_cmd_ok("ip", &["link", "set", to, "down"]);
+            already_gone(run_cmd("ip", &["link", "set", to, "name", from]).map(|_| ()))
+        }
+        Change::LinkUp { name } => {
+            already_gone(run_cmd("ip", &["link", "set", name, "down"]).map(|_| ()))
+        }
+        Change::HostRoute { ip, dev } => already_gone(delete_server_host_route_via(*ip, dev)),
+    }
 }
 
-fn get_tun_ip_mask() -> Option<(String, String)> {
-    let out = run_cmd("ip", &["-o", "addr", "show", OPKG_TUN_NAME]).ok()?;
+/// Treat an `ip` error saying the link or route no longer exists as done,
+/// so such entries do not stay in the journal forever.
+fn already_gone(result: Result<(), String>) -> Result<(), String> {
+    match result {
+        Err(e)
+            if e.contains("No such process")
+                || e.contains("Cannot find device")
+                || e.contains("No such device") =>
+        {
+            Ok(())
+        }
+        other => other,
+    }
+}
+
+fn get_tun_ip_mask(names: &InterfaceNames) -> Option<(String, String)> {
+    let out = run_cmd("ip", &["-o", "addr", "show", &names.opkg_tun]).ok()?;
     for line in out.lines() {
         let parts: Vec<&str> = line.split_whitespace().collect();
         if let Some(i) = parts.iter().position(|&p| p == "inet") {
@@ -248,8 +343,8 @@ fn get_tun_ip_mask() -> Option<(String, String)> {
     None
 }
 
-fn get_tun_mtu() -> Option<u16> {
-    let out = run_cmd("ip", &["-o", "link", "show", OPKG_TUN_NAME]).ok()?;
+fn get_tun_mtu(names: &InterfaceNames) -> Option<u16> {
+    let out = run_cmd("ip", &["-o", "link", "show", &names.opkg_tun]).ok()?;
     let parts: Vec<&str> = out.split_whitespace().collect();
     let idx = parts.iter().position(|&p| p == "mtu")?;
     parts.get(idx + 1)?.parse::<u16>().ok()
@@ -270,65 +365,278 @@ fn prefix_to_netmask(prefix: u8) -> String {
     )
 }
 
-fn ensure_ndm_interface_object() -> Result<(), String> {
+fn ensure_ndm_interface_object(
+    names: &InterfaceNames,
+    journal: &Mutex<Journal>,
+) -> Result<(), String> {
     let msg = format!(
-        "[routing] ensuring {} in NDM (ndmc={})",
-        NDM_IF_NAME,
-        find_ndmc()
+        "[routing] ensuring {} in NDM ({})",
+        &names.ndm,
+        ndm::backend_name()
     );
     log::info!("{}", msg);
     crate::logs::global_buffer().push(msg);
 
     // Create NDM interface object first.
-    // ndmc() already has retries and transient error handling.
-    ndmc_required(&format!("interface {}", NDM_IF_NAME))?;
+    // ndm::run() already has retries and transient error handling.
+    let name = names.ndm.clone();
+    let existed = ndm::show_interface(&name)
+        .map(|info| !info.id.is_empty())
+        .unwrap_or(false);
+    ndm_required(&NdmCommand::Interface { name: name.clone() })?;
+    // An object that was already there (ours from an earlier run, or an
+    // adopted one) is not ours to delete on rollback.
+    if !existed {
+        journal
+            .lock()
+            .unwrap()
+            .record(Change::NdmInterface { name: name.clone() });
+    }
+    // The description goes away with the interface object, so it is not journaled.
+    ndm_required(&NdmCommand::InterfaceDescription {
+        name,
+        description: names.owner.clone(),
+    })?;
     Ok(())
 }
 
-fn apply_ndm_interface_settings() -> Result<(), String> {
-    if let Some((ip, mask)) = get_tun_ip_mask() {
-        ndmc_required(&format!(
-            "interface {} ip address {} {}",
-            NDM_IF_NAME, ip, mask
-        ))?;
+/// Description of `names.ndm` when it exists in NDM but belongs to someone
+/// else: not tagged with our owner and not in our journal. Query failures
+/// (including "no such interface") count as no collision.
+fn foreign_ndm_interface(names: &InterfaceNames, journal: &Mutex<Journal>) -> Option<String> {
+    let info = ndm::show_interface(&names.ndm).ok()?;
+    if !is_foreign(names, &info) {
+        return None;
+    }
+    let journaled = journal
+        .lock()
+        .unwrap()
+        .entries()
+        .contains(&Change::NdmInterface {
+            name: names.ndm.clone(),
+        });
+    if journaled {
+        None
+    } else {
+        Some(info.description)
+    }
+}
+
+/// Whether an existing NDM interface belongs to someone else. Wrappers
+/// before owner tags created `OpkgTun0` without a description; such an
+/// object is adopted (and tagged) instead of blocking setup.
+fn is_foreign(names: &InterfaceNames, info: &InterfaceInfo) -> bool {
+    if info.id.is_empty() || info.description == names.owner {
+        return false;
     }
-    if let Some(mtu) = get_tun_mtu() {
-        ndmc_required(&format!("interface {} ip mtu {}", NDM_IF_NAME, mtu))?;
+    let legacy = names.ndm == InterfaceNames::for_index(0).ndm && info.description.is_empty();
+    !legacy
+}
+
+/// Address, MTU and security settings live on the NDM interface object and
+/// are reverted together with it, so only the `up` state is journaled.
+pub fn apply_ndm_interface_settings(
+    names: &InterfaceNames,
+    journal: &Mutex<Journal>,
+) -> Result<(), String> {
+    let name = names.ndm.clone();
+    if let Some((address, mask)) = get_tun_ip_mask(names) {
+        ndm_required(&NdmCommand::InterfaceAddress {
+            name: name.clone(),
+            address,
+            mask,
+        })?;
+    }
+    if let Some(mtu) = get_tun_mtu(names) {
+        ndm_required(&NdmCommand::InterfaceMtu {
+            name: name.clone(),
+            mtu,
+        })?;
     }
 
-    ndmc_required(&format!("interface {} ip global auto", NDM_IF_NAME))?;
-    ndmc_required(&format!("interface {} ip tcp adjust-mss pmtu", NDM_IF_NAME))?;
-    ndmc_required(&format!("interface {} security-level public", NDM_IF_NAME))?;
-    ndmc_required(&format!("interface {} up", NDM_IF_NAME))?;
+    ndm_required(&NdmCommand::InterfaceGlobalAuto { name: name.clone() })?;
+    ndm_required(&NdmCommand::InterfaceAdjustMss { name: name.clone() })?;
+    ndm_required(&NdmCommand::InterfaceSecurityPublic { name: name.clone() })?;
+    ndm_journaled(
+        NdmCommand::InterfaceUp { name: name.clone() },
+        NdmCommand::InterfaceDown { name },
+        journal,
+    )?;
     Ok(())
 }
 
-fn set_ndm_default_routes() -> Result<(), String> {
+fn set_ndm_default_routes(names: &InterfaceNames, journal: &Mutex<Journal>) -> Result<(), String> {
+    let name = names.ndm.clone();
     // Shell implementation uses interface-based default route.
-    ndmc_required(&format!("ip route default {}", NDM_IF_NAME))?;
+    ndm_journaled(
+        NdmCommand::DefaultRoute {
+            name: name.clone(),
+            ipv6: false,
+        },
+        NdmCommand::NoDefaultRoute {
+            name: name.clone(),
+            ipv6: false,
+        },
+        journal,
+    )?;
     // IPv6 default route can fail on some configs/firmware, do not fail whole setup.
-    ndmc_soft(&format!("ipv6 route default {}", NDM_IF_NAME));
+    let v6 = NdmCommand::DefaultRoute {
+        name: name.clone(),
+        ipv6: true,
+    };
+    if ndm_soft(&v6) {
+        journal.lock().unwrap().record(Change::NdmCommand {
+            command: v6,
+            undo: NdmCommand::NoDefaultRoute { name, ipv6: true },
+        });
+    }
     Ok(())
 }
 
-fn assert_ndm_default_route() -> Result<(), String> {
-    if verify_kernel_default_route_via_opkgtun() {
+/// Route `prefixes` via the tunnel. Individual prefixes NDM rejects are
+/// skipped; the setup only fails when none could be added.
+fn set_ndm_prefix_routes(
+    names: &InterfaceNames,
+    prefixes: &[String],
+    journal: &Mutex<Journal>,
+) -> Result<(), String> {
+    let mut added = 0;
+    for prefix in prefixes {
+        let cmd = NdmCommand::Route {
+            name: names.ndm.clone(),
+            prefix: prefix.clone(),
+        };
+        if ndm_soft(&cmd) {
+            journal.lock().unwrap().record(Change::NdmCommand {
+                command: cmd,
+                undo: NdmCommand::NoRoute {
+                    name: names.ndm.clone(),
+                    prefix: prefix.clone(),
+                },
+            });
+            added += 1;
+        }
+    }
+    let msg = format!(
+        "[routing] {}/{} prefix routes via {}",
+        added,
+        prefixes.len(),
+        names.ndm
+    );
+    log::info!("{}", msg);
+    crate::logs::global_buffer().push(msg);
+    if added == 0 && !prefixes.is_empty() {
+        return Err(format!("NDM rejected every prefix route via {}", names.ndm));
+    }
+    Ok(())
+}
+
+/// What NDM itself reports about the tunnel interface and its routes.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct NdmDiagnostics {
+    pub backend: String,
+    pub interface: Option<InterfaceInfo>,
+    pub routes_v4: Vec<RouteEntry>,
+    pub routes_v6: Vec<RouteEntry>,
+    pub default_route_v4: bool,
+    pub default_route_v6: bool,
+    pub errors: Vec<String>,
+}
+
+/// Collect `show interface`, `show ip route` and `show ipv6 route` for the
+/// NDM tunnel interface. Query failures are reported in `errors`.
+pub fn ndm_diagnostics(names: &InterfaceNames) -> NdmDiagnostics {
+    let mut diag = NdmDiagnostics {
+        backend: ndm::backend_name(),
+        ..Default::default()
+    };
+    match ndm::show_interface(&names.ndm) {
+        Ok(info) => diag.interface = Some(info),
+        Err(e) => diag
+            .errors
+            .push(format!("show interface {}: {}", &names.ndm, e)),
+    }
+    match ndm::show_routes(false) {
+        Ok(routes) => diag.routes_v4 = routes,
+        Err(e) => diag.errors.push(format!("show ip route: {}", e)),
+    }
+    match ndm::show_routes(true) {
+        Ok(routes) => diag.routes_v6 = routes,
+        Err(e) => diag.errors.push(format!("show ipv6 route: {}", e)),
+    }
+    let via_tunnel = |r: &RouteEntry| r.is_default() && r.interface == names.ndm;
+    diag.default_route_v4 = diag.routes_v4.iter().any(via_tunnel);
+    diag.default_route_v6 = diag.routes_v6.iter().any(via_tunnel);
+    diag
+}
+
+/// Poll NDM until it reports the tunnel interface up with the IPv4 default
+/// route on it. `Err` means NDM could not be queried at all, or its route
+/// table could not be read (a router always has some routes).
+fn wait_ndm_default_route(names: &InterfaceNames) -> Result<bool, String> {
+    let start = std::time::Instant::now();
+    loop {
+        let iface = ndm::show_interface(&names.ndm).map_err(|e| e.to_string())?;
+        let routes = ndm::show_routes(false).map_err(|e| e.to_string())?;
+        if routes.is_empty() {
+            return Err("route table empty or in an unknown format".into());
+        }
+        let has_route = routes
+            .iter()
+            .any(|r| r.is_default() && r.interface == names.ndm);
+        if iface.is_up() && has_route {
+            return Ok(true);
+        }
+        if start.elapsed() >= NDM_VERIFY_TIMEOUT {
+            let msg = format!(
+                "[routing] NDM view of {}: state={} link={} default_route={}",
+                &names.ndm, iface.state, iface.link, has_route
+            );
+            log::warn!("{}", msg);
+            crate::logs::global_buffer().push(msg);
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(300));
+    }
+}
+
+fn assert_ndm_default_route(names: &InterfaceNames) -> Result<(), String> {
+    match wait_ndm_default_route(names) {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(format!(
+                "NDM does not report {} up with an active default route",
+                &names.ndm
+            ))
+        }
+        Err(e) => {
+            let msg = format!(
+                "[routing] cannot query NDM state ({}); relying on kernel table",
+                e
+            );
+            log::warn!("{}", msg);
+            crate::logs::global_buffer().push(msg);
+        }
+    }
+
+    if verify_kernel_default_route_via_opkgtun(names) {
         Ok(())
     } else {
         let msg = format!(
             "[routing] default route via {} not visible in kernel table yet; checking connectivity fallback",
-            OPKG_TUN_NAME
+            &names.opkg_tun
         );
         log::warn!("{}", msg);
         crate::logs::global_buffer().push(msg);
 
         if check_connectivity(
+            &names.opkg_tun,
             "http://connectivitycheck.gstatic.com/generate_204",
             Duration::from_secs(5),
         ) {
             let ok = format!(
                 "[routing] connectivity probe via {} succeeded despite kernel table mismatch",
-                OPKG_TUN_NAME
+                &names.opkg_tun
             );
             log::info!("{}", ok);
             crate::logs::global_buffer().push(ok);
@@ -336,16 +644,16 @@ fn assert_ndm_default_route() -> Result<(), String> {
         } else {
             Err(format!(
                 "default route via {} is not active in kernel routing table and connectivity probe failed",
-                OPKG_TUN_NAME
+                &names.opkg_tun
             ))
         }
     }
 }
 
-fn wait_for_tun() -> bool {
+fn wait_for_tun(names: &InterfaceNames) -> bool {
     let start = std::time::Instant::now();
     while start.elapsed() < TUN_WAIT_TIMEOUT {
-        if std::path::Path::new(&format!("/sys/class/net/{}", TUN_NAME)).exists() {
+        if std::path::Path::new(&format!("/sys/class/net/{}", &names.tun)).exists() {
             return true;
         }
         std::thread::sleep(TUN_POLL_INTERVAL);
@@ -353,10 +661,10 @@ fn wait_for_tun() -> bool {
     false
 }
 
-fn wait_for_tun_retry() -> bool {
+fn wait_for_tun_retry(names: &InterfaceNames) -> bool {
     let start = std::time::Instant::now();
     while start.elapsed() < TUN_RENAME_RETRY_TIMEOUT {
-        if std::path::Path::new(&format!("/sys/class/net/{}", TUN_NAME)).exists() {
+        if std::path::Path::new(&format!("/sys/class/net/{}", &names.tun)).exists() {
             return true;
         }
         std::thread::sleep(TUN_RENAME_RETRY_POLL_INTERVAL);
@@ -364,66 +672,118 @@ fn wait_for_tun_retry() -> bool {
     false
 }
 
+/// Which traffic a tunnel carries through NDM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteScope {
+    /// The tunnel owns the NDM default route.
+    Default,
+    /// Only these CIDR prefixes are routed through the tunnel.
+    Prefixes(Vec<String>),
+}
+
 /// Configure interface + NDM routing after the VPN client creates tun0.
-/// Renames tun0 → opkgtun0 and sets default route via NDM.
-/// Returns the detected WAN interface name on success.
-pub fn setup_routing(server_addresses: &[String]) -> Result<String, String> {
-    log::info!("[routing] waiting for {} ...", TUN_NAME);
+/// Renames tun0 → opkgtun0 and sets default route (or `scope` prefix
+/// routes) via NDM.
+/// Returns the detected WAN interface name on success. Every applied step is
+/// recorded in `journal`; if a later step fails, all of them are undone.
+pub fn setup_routing(
+    names: &InterfaceNames,
+    scope: &RouteScope,
+    server_ips: &[IpAddr],
+    journal: &Mutex<Journal>,
+) -> Result<String, String> {
+    let result = apply_routing(names, scope, server_ips, journal);
+    if let Err(e) = &result {
+        let msg = format!(
+            "[routing] setup failed, rolling back applied changes: {}",
+            e
+        );
+        log::warn!("{}", msg);
+        crate::logs::global_buffer().push(msg);
+        journal.lock().unwrap().unwind(undo_change);
+    }
+    result
+}
 
-    if !wait_for_tun() {
+fn apply_routing(
+    names: &InterfaceNames,
+    scope: &RouteScope,
+    server_ips: &[IpAddr],
+    journal: &Mutex<Journal>,
+) -> Result<String, String> {
+    log::info!("[routing] waiting for {} ...", &names.tun);
+
+    if !wait_for_tun(names) {
         return Err(format!(
             "{} did not appear within {}s",
-            TUN_NAME,
+            &names.tun,
             TUN_WAIT_TIMEOUT.as_secs()
         ));
     }
     std::thread::sleep(Duration::from_millis(500));
 
     // Clear possible leftover backup from a previous interrupted rename flow.
-    run_cmd_ok("ip", &["link", "del", OPKG_TUN_BACKUP_NAME]);
+    run_cmd_ok("ip", &["link", "del", &names.opkg_backup]);
+
+    if let Some(description) = foreign_ndm_interface(names, journal) {
+        return Err(format!(
+            "{} already exists in NDM and is not managed by this instance (description '{}'); \
+             set instance.index or instance.ndm_name to a free OpkgTunN",
+            names.ndm, description
+        ));
+    }
 
     // Create NDM object before Linux rename. On some Keenetic builds this avoids
     // OpkgTun creation failure when opkgtun0 already exists.
-    ensure_ndm_interface_object()?;
+    ensure_ndm_interface_object(names, journal)?;
 
-    let tun_exists = std::path::Path::new(&format!("/sys/class/net/{}", TUN_NAME)).exists();
-    let opkg_exists = std::path::Path::new(&format!("/sys/class/net/{}", OPKG_TUN_NAME)).exists();
+    let tun_exists = std::path::Path::new(&format!("/sys/class/net/{}", &names.tun)).exists();
+    let opkg_exists = std::path::Path::new(&format!("/sys/class/net/{}", &names.opkg_tun)).exists();
 
     if tun_exists {
-        log::info!("[routing] renaming {} → {}", TUN_NAME, OPKG_TUN_NAME);
-        run_cmd("ip", &["link", "set", TUN_NAME, "down"])?;
-        match run_cmd("ip", &["link", "set", TUN_NAME, "name", OPKG_TUN_NAME]) {
-            Ok(_) => {}
+        log::info!("[routing] renaming {} → {}", &names.tun, &names.opkg_tun);
+        run_cmd("ip", &["link", "set", &names.tun, "down"])?;
+        match run_cmd("ip", &["link", "set", &names.tun, "name", &names.opkg_tun]) {
+            Ok(_) => {
+                journal.lock().unwrap().record(Change::LinkRename {
+                    from: names.tun.clone(),
+                    to: names.opkg_tun.clone(),
+                });
+            }
             Err(e) => {
                 let opkg_now_exists =
-                    std::path::Path::new(&format!("/sys/class/net/{}", OPKG_TUN_NAME)).exists();
+                    std::path::Path::new(&format!("/sys/class/net/{}", &names.opkg_tun)).exists();
                 if e.to_ascii_lowercase().contains("file exists") && opkg_now_exists {
                     let msg = format!(
                         "[routing] {} already exists, replacing with fresh {}: {}",
-                        OPKG_TUN_NAME, TUN_NAME, e
+                        &names.opkg_tun, &names.tun, e
                     );
                     log::warn!("{}", msg);
                     crate::logs::global_buffer().push(msg);
-                    if !wait_for_tun_retry() {
+                    if !wait_for_tun_retry(names) {
                         return Err(format!(
                             "{} disappeared before rename retry after {} conflict",
-                            TUN_NAME, OPKG_TUN_NAME
+                            &names.tun, &names.opkg_tun
                         ));
                     }
 
                     // Two-phase swap with rollback: preserve existing opkgtun0 until
                     // fresh tun0 is successfully moved into place.
-                    run_cmd_ok("ip", &["link", "set", OPKG_TUN_NAME, "down"]);
-                    run_cmd("ip", &["link", "set", OPKG_TUN_NAME, "name", OPKG_TUN_BACKUP_NAME])?;
+                    run_cmd_ok("ip", &["link", "set", &names.opkg_tun, "down"]);
+                    run_cmd("ip", &["link", "set", &names.opkg_tun, "name", &names.opkg_backup])?;
 
-                    match run_cmd("ip", &["link", "set", TUN_NAME, "name", OPKG_TUN_NAME]) {
+                    match run_cmd("ip", &["link", "set", &names.tun, "name", &names.opkg_tun]) {
                         Ok(_) => {
-                            run_cmd_ok("ip", &["link", "del", OPKG_TUN_BACKUP_NAME]);
+                            run_cmd_ok("ip", &["link", "del", &names.opkg_backup]);
+                            journal.lock().unwrap().record(Change::LinkRename {
+                                from: names.tun.clone(),
+                                to: names.opkg_tun.clone(),
+                            });
                         }
                         Err(rename_err) => {
                             let rollback = run_cmd(
                                 "ip",
-                                &["link", "set", OPKG_TUN_BACKUP_NAME, "name", OPKG_TUN_NAME],
+                                &["link", "set", &names.opkg_backup, "name", &names.opkg_tun],
                             );
                             if let Err(rb_err) = rollback {
                                 log::error!(
@@ -439,74 +799,141 @@ pub fn setup_routing(server_addresses: &[String]) -> Result<String, String> {
                 }
             }
         }
-        run_cmd("ip", &["link", "set", OPKG_TUN_NAME, "up"])?;
+        run_cmd("ip", &["link", "set", &names.opkg_tun, "up"])?;
+        journal.lock().unwrap().record(Change::LinkUp {
+            name: names.opkg_tun.clone(),
+        });
     } else if opkg_exists {
         let msg = format!(
             "[routing] {} already present and {} is absent, skipping rename",
-            OPKG_TUN_NAME, TUN_NAME
+            &names.opkg_tun, &names.tun
         );
         log::info!("{}", msg);
         crate::logs::global_buffer().push(msg);
-        run_cmd_ok("ip", &["link", "set", OPKG_TUN_NAME, "up"]);
+        if run_cmd("ip", &["link", "set", &names.opkg_tun, "up"]).is_ok() {
+            journal.lock().unwrap().record(Change::LinkUp {
+                name: names.opkg_tun.clone(),
+            });
+        }
     } else {
         return Err(format!(
             "neither {} nor {} exists after wait stage",
-            TUN_NAME, OPKG_TUN_NAME
+            &names.tun, &names.opkg_tun
         ));
     }
 
     // Apply runtime params after opkgtun0 exists and has IP/MTU.
-    apply_ndm_interface_settings()?;
+    apply_ndm_interface_settings(names, journal)?;
 
     let wan_if = current_wan_interface().ok_or("failed to detect WAN interface")?;
     log::info!("[routing] WAN interface: {}", wan_if);
 
     // Route VPN-server traffic through WAN to avoid a routing loop
-    for ip in extract_server_ips(server_addresses) {
-        delete_server_host_route(ip);
-        if let Err(e) = add_server_host_route(ip, &wan_if) {
-            log::warn!("[routing] server route {}: {}", ip, e);
-        }
+    for &ip in server_ips {
+        install_server_host_route(ip, &wan_if, journal);
     }
 
-    log::info!("[routing] setting default route via {}", OPKG_TUN_NAME);
-    set_ndm_default_routes()?;
-    assert_ndm_default_route()?;
+    match scope {
+        RouteScope::Default => {
+            log::info!("[routing] setting default route via {}", &names.opkg_tun);
+            set_ndm_default_routes(names, journal)?;
+            assert_ndm_default_route(names)?;
+        }
+        RouteScope::Prefixes(prefixes) => set_ndm_prefix_routes(names, prefixes, journal)?,
+    }
 
     log::info!("[routing] setup complete (WAN={})", wan_if);
     crate::logs::global_buffer().push(format!("[routing] setup complete (WAN={})", wan_if));
     Ok(wan_if)
 }
 
+fn install_server_host_route(ip: IpAddr, wan_if: &str, journal: &Mutex<Journal>) {
+    delete_server_host_route(ip);
+    let mut journal = journal.lock().unwrap();
+    journal.forget(|c| matches!(c, Change::HostRoute { ip: old, .. } if *old == ip));
+    match add_server_host_route(ip, wan_if) {
+        Ok(()) => journal.record(Change::HostRoute {
+            ip,
+            dev: wan_if.to_string(),
+        }),
+        Err(e) => log::warn!("[routing] server route {} via {}: {}", ip, wan_if, e),
+    }
+}
+
+/// Re-add the server host routes and the tunnel's NDM routes for `scope`
+/// without removing anything first.
+pub fn reassert_routes(
+    names: &InterfaceNames,
+    scope: &RouteScope,
+    server_ips: &[IpAddr],
+    wan_if: &str,
+    journal: &Mutex<Journal>,
+) -> Result<(), String> {
+    for &ip in server_ips {
+        install_server_host_route(ip, wan_if, journal);
+    }
+    match scope {
+        RouteScope::Default => set_ndm_default_routes(names, journal),
+        RouteScope::Prefixes(prefixes) => set_ndm_prefix_routes(names, prefixes, journal),
+    }
+}
+
 /// Update only the server routes to go through a new WAN interface.
 /// Does NOT touch the TUN device, iptables, or NDM.
-pub fn reroute_server_via_wan(server_addresses: &[String], new_wan: &str) {
+pub fn reroute_server_via_wan(server_ips: &[IpAddr], new_wan: &str, journal: &Mutex<Journal>) {
     log::info!("[routing] re-routing server IPs via {}", new_wan);
-    for ip in extract_server_ips(server_addresses) {
-        delete_server_host_route(ip);
-        if let Err(e) = add_server_host_route(ip, new_wan) {
-            log::warn!("[routing] server route {} via {}: {}", ip, new_wan, e);
-        }
+    for &ip in server_ips {
+        install_server_host_route(ip, new_wan, journal);
     }
     let msg = format!("[routing] server routes updated to WAN={}", new_wan);
     log::info!("{}", msg);
     crate::logs::global_buffer().push(msg);
 }
 
+/// Bring server host routes in line with a re-resolved IP set: routes for
+/// IPs that disappeared are removed, new IPs get a route via `wan_if`.
+pub fn update_server_routes(server_ips: &[IpAddr], wan_if: &str, journal: &Mutex<Journal>) {
+    let current: Vec<IpAddr> = journal
+        .lock()
+        .unwrap()
+        .entries()
+        .iter()
+        .filter_map(|c| match c {
+            Change::HostRoute { ip, .. } => Some(*ip),
+            _ => None,
+        })
+        .collect();
+
+    let stale: Vec<IpAddr> = current
+        .iter()
+        .copied()
+        .filter(|ip| !server_ips.contains(ip))
+        .collect();
+    if !stale.is_empty() {
+        journal.lock().unwrap().unwind_where(
+            |c| matches!(c, Change::HostRoute { ip, .. } if stale.contains(ip)),
+            undo_change,
+        );
+    }
+    for &ip in server_ips.iter().filter(|ip| !current.contains(ip)) {
+        install_server_host_route(ip, wan_if, journal);
+    }
+}
+
 // --------------- watchdog helpers ---------------
 
-pub fn is_tun_alive() -> bool {
-    std::path::Path::new(&format!("/sys/class/net/{}", OPKG_TUN_NAME)).exists()
+pub fn is_tun_alive(names: &InterfaceNames) -> bool {
+    std::path::Path::new(&format!("/sys/class/net/{}", &names.opkg_tun)).exists()
 }
 
-pub fn check_connectivity(check_url: &str, timeout: Duration) -> bool {
+pub fn check_connectivity(dev: &str, check_url: &str, timeout: Duration) -> bool {
     let timeout_secs = timeout.as_secs().max(1);
     let connect_timeout = timeout_secs.to_string();
     let max_time = (timeout_secs + 2).to_string();
 
     let args_owned = vec![
         "--interface".to_string(),
-        OPKG_TUN_NAME.to_string(),
+        dev.to_string(),
         "--connect-timeout".to_string(),
         connect_timeout,
         "--max-time".to_string(),
@@ -543,17 +970,223 @@ pub fn check_connectivity(check_url: &str, timeout: Duration) -> bool {
 
 // --------------- teardown ---------------
 
-/// Bring the tunnel link down and clear temporary server routes.
-pub fn teardown_routing(server_addresses: &[String]) {
-    log::info!("[routing] tearing down ...");
+/// How much of the routing setup `teardown_routing` removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeardownLevel {
+    /// Clear server routes and bring the link down. NDM objects stay in
+    /// place (and in the journal) so a reconnect can reuse them.
+    Soft,
+    /// Undo the whole journal, remove the NDM default routes and the
+    /// interface object, and delete the Linux links, like the init script's `stop`.
+    Full,
+}
 
-    for ip in extract_server_ips(server_addresses) {
-        delete_server_host_route(ip);
-    }
+/// Tear down routing at the given level.
+pub fn teardown_routing(names: &InterfaceNames, journal: &Mutex<Journal>, level: TeardownLevel) {
+    log::info!("[routing] tearing down ({:?}) ...", level);
 
-    // Shell behavior: bring interface down and recreate it on next start.
-    run_cmd_ok("ip", &["link", "set", OPKG_TUN_NAME, "down"]);
+    match level {
+        TeardownLevel::Soft => {
+            journal.lock().unwrap().unwind_where(
+                |c| matches!(c, Change::HostRoute { .. } | Change::LinkUp { .. }),
+                undo_change,
+            );
+            // Shell behavior: bring interface down and recreate it on next start.
+            run_cmd_ok("ip", &["link", "set", &names.opkg_tun, "down"]);
+        }
+        TeardownLevel::Full => {
+            journal.lock().unwrap().unwind(undo_change);
+            for item in remove_leftovers(names, true) {
+                log::info!("[routing] removed {}", item);
+            }
+        }
+    }
 
     log::info!("[routing] teardown complete");
     crate::logs::global_buffer().push("[routing] teardown complete".into());
 }
+
+fn link_exists(name: &str) -> bool {
+    std::path::Path::new(&format!("/sys/class/net/{}", name)).exists()
+}
+
+fn running_config_has(config: &[String], cmd: &NdmCommand) -> bool {
+    let line = cmd.to_string();
+    config.iter().any(|l| l.trim() == line)
+}
+
+/// Remove links and NDM entries the journal does not (or no longer) know
+/// about. `tun0` belongs to the client and is only deleted when asked.
+fn remove_leftovers(names: &InterfaceNames, include_client_link: bool) -> Vec<String> {
+    let mut removed = Vec::new();
+
+    // Never touch an OpkgTunN (or its Linux link) that another package owns.
+    let foreign = match ndm::show_interface(&names.ndm) {
+        Ok(info) => is_foreign(names, &info),
+        Err(_) => false,
+    };
+    if foreign {
+        let msg = format!(
+            "[routing] {} is owned by another package, leaving it in place",
+            names.ndm
+        );
+        log::warn!("{}", msg);
+        crate::logs::global_buffer().push(msg);
+    }
+
+    let mut links = Vec::new();
+    if !foreign {
+        links.extend([&names.opkg_tun, &names.opkg_backup]);
+    }
+    if include_client_link {
+        links.push(&names.tun);
+    }
+    for link in links {
+        if link_exists(link) {
+            run_cmd_ok("ip", &["link", "del", link]);
+            removed.push(format!("link {}", link));
+        }
+    }
+
+    match ndm::running_config() {
+        Ok(_) if foreign => {}
+        Ok(config) => {
+            let name = names.ndm.clone();
+            // DNS servers bound to our interface by `dns.mode = "ndm"`.
+            for server in config.iter().filter_map(|l| NameServer::parse(l)) {
+                if server.interface.as_deref() == Some(name.as_str())
+                    && ndm_soft(&NdmCommand::NoNameServer(server.clone()))
+                {
+                    removed.push(format!("ndm '{}'", server));
+                }
+            }
+            for (entry, undo) in [
+                (
+                    NdmCommand::DefaultRoute {
+                        name: name.clone(),
+                        ipv6: false,
+                    },
+                    NdmCommand::NoDefaultRoute {
+                        name: name.clone(),
+                        ipv6: false,
+                    },
+                ),
+                (
+                    NdmCommand::DefaultRoute {
+                        name: name.clone(),
+                        ipv6: true,
+                    },
+                    NdmCommand::NoDefaultRoute {
+                        name: name.clone(),
+                        ipv6: true,
+                    },
+                ),
+                (
+                    NdmCommand::Interface { name: name.clone() },
+                    NdmCommand::NoInterface { name: name.clone() },
+                ),
+            ] {
+                if running_config_has(&config, &entry) && ndm_soft(&undo) {
+                    removed.push(format!("ndm '{}'", entry));
+                }
+            }
+        }
+        Err(e) => log::warn!("[routing] cannot read NDM running-config: {}", e),
+    }
+
+    removed
+}
+
+// --------------- crash recovery ---------------
+
+/// Reconcile routing state left behind by a previous run that did not shut
+/// down cleanly (OOM kill, `panic = "abort"`, power loss). Undoes everything
+/// still in the journal, then removes leftovers the journal cannot know about.
+/// Returns a description of each removed item.
+pub fn recover_stale_state(names: &InterfaceNames, journal: &Mutex<Journal>) -> Vec<String> {
+    let mut removed: Vec<String> = Vec::new();
+
+    removed.extend(
+        journal
+            .lock()
+            .unwrap()
+            .unwind(undo_change)
+            .iter()
+            .map(|c| c.to_string()),
+    );
+    removed.extend(remove_leftovers(names, false));
+
+    for item in &removed {
+        let msg = format!("[recovery] removed stale {}", item);
+        log::info!("{}", msg);
+        crate::logs::global_buffer().push(msg);
+    }
+    if removed.is_empty() {
+        log::info!("[recovery] no stale routing state found");
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_host() {
+        assert_eq!(endpoint_host("vpn.example.com:443"), "vpn.example.com");
+        assert_eq!(endpoint_host("vpn.example.com"), "vpn.example.com");
+        assert!(has_hostnames(&[
+            "1.2.3.4:443".into(),
+            "vpn.example.com".into()
+        ]));
+        assert!(!has_hostnames(&[
+            "1.2.3.4:443".into(),
+            "[2001:db8::1]:443".into()
+        ]));
+    }
+
+    #[test]
+    fn test_interface_names_from_settings() {
+        let names = InterfaceNames::from_settings(&InstanceSettings {
+            index: 1,
+            ndm_name: "OpkgTun5".into(),
+            ..Default::default()
+        });
+        assert_eq!(names.tun, "tun1");
+        assert_eq!(names.opkg_tun, "opkgtun1");
+        assert_eq!(names.opkg_backup, "opkgbak1");
+        assert_eq!(names.ndm, "OpkgTun5");
+        assert_eq!(names.owner, "trusttunnel-keenetic-1");
+        assert_eq!(
+            InterfaceNames::from_settings(&InstanceSettings::default()),
+            InterfaceNames::for_index(0)
+        );
+        let toml = crate::config::generate_client_toml(&Default::default(), &names.tun);
+        assert!(toml.contains("[listener.tun]\nname = \"tun1\"\n"));
+
+        let info = |description: &str| InterfaceInfo {
+            id: "OpkgTun0".into(),
+            description: description.into(),
+            ..Default::default()
+        };
+        let legacy = InterfaceNames::for_index(0);
+        assert!(!is_foreign(&legacy, &info("")));
+        assert!(!is_foreign(&legacy, &info("trusttunnel-keenetic-0")));
+        assert!(is_foreign(&legacy, &info("wireguard")));
+        assert!(is_foreign(&InterfaceNames::for_index(2), &info("")));
+    }
+
+    #[test]
+    fn test_running_config_has() {
+        let config: Vec<String> = "interface OpkgTun0\n    up\n!\nip route default OpkgTun0"
+            .lines()
+            .map(String::from)
+            .collect();
+        let route = |ipv6| NdmCommand::DefaultRoute {
+            name: "OpkgTun0".into(),
+            ipv6,
+        };
+        assert!(running_config_has(&config, &route(false)));
+        assert!(!running_config_has(&config, &route(true)));
+    }
+}
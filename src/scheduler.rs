@@ -0,0 +1,384 @@
+use crate::config::{ScheduleSettings, ScheduleState};
+use crate::logs;
+use crate::registry::TunnelRegistry;
+use crate::sessions::StopReason;
+use crate::util::unix_now;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const TICK: Duration = Duration::from_secs(15);
+const DAY: i64 = 86_400;
+/// How far ahead the next change is searched for: a full week plus a day
+/// for windows that run past midnight.
+const LOOKAHEAD_MINUTES: i64 = 8 * 24 * 60;
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Window {
+    /// Bit 0 is Monday.
+    days: u8,
+    start: i64,
+    end: i64,
+    state: ScheduleState,
+}
+
+impl Window {
+    fn on(&self, weekday: i64) -> bool {
+        self.days & (1 << weekday.rem_euclid(7)) != 0
+    }
+
+    fn contains(&self, weekday: i64, minute: i64) -> bool {
+        if self.start < self.end {
+            self.on(weekday) && (self.start..self.end).contains(&minute)
+        } else if self.start > self.end {
+            (self.on(weekday) && minute >= self.start)
+                || (self.on(weekday - 1) && minute < self.end)
+        } else {
+            self.on(weekday)
+        }
+    }
+}
+
+/// How Unix time maps to local time.
+#[derive(Debug, Clone, Copy)]
+enum Zone {
+    /// `utc_offset`: the same offset all year.
+    Fixed(i64),
+    /// Offset at a given Unix time, which follows daylight saving time.
+    Local(fn(u64) -> i64),
+}
+
+impl Zone {
+    fn offset_at(&self, now: u64) -> i64 {
+        match self {
+            Zone::Fixed(secs) => *secs,
+            Zone::Local(offset) => offset(now),
+        }
+    }
+}
+
+/// Offset of the process time zone (`TZ`, set from `schedule.timezone` at
+/// startup, or the system zone) at `now`.
+fn local_offset(now: u64) -> i64 {
+    let time = now as nix::libc::time_t;
+    // SAFETY: `tm` is plain data and `localtime_r` only writes into it.
+    let mut tm: nix::libc::tm = unsafe { std::mem::zeroed() };
+    let result = unsafe { nix::libc::localtime_r(&time, &mut tm) };
+    if result.is_null() {
+        0
+    } else {
+        tm.tm_gmtoff as i64
+    }
+}
+
+/// Parsed weekly schedule.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    zone: Zone,
+    default_state: ScheduleState,
+    windows: Vec<Window>,
+}
+
+fn parse_offset(s: &str) -> Result<i64, String> {
+    let err = || format!("invalid utc_offset '{}', use +HH:MM or -HH:MM", s);
+    let s = s.trim();
+    let (sign, rest) = if let Some(rest) = s.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = s.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err(err());
+    };
+    let minutes = parse_time(rest).map_err(|_| err())?;
+    if minutes > 14 * 60 {
+        return Err(err());
+    }
+    Ok(sign * minutes * 60)
+}
+
+/// `HH:MM` as minutes since midnight.
+fn parse_time(s: &str) -> Result<i64, String> {
+    let err = || format!("invalid time '{}', use HH:MM", s);
+    let (h, m) = s.trim().split_once(':').ok_or_else(err)?;
+    let h: i64 = h.parse().map_err(|_| err())?;
+    let m: i64 = m.parse().map_err(|_| err())?;
+    if !(0..24).contains(&h) || !(0..60).contains(&m) {
+        return Err(err());
+    }
+    Ok(h * 60 + m)
+}
+
+fn parse_days(days: &[String]) -> Result<u8, String> {
+    if days.is_empty() {
+        return Ok(0x7f);
+    }
+    let index = |name: &str| {
+        DAY_NAMES
+            .iter()
+            .position(|d| d.eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| format!("invalid day '{}', use mon … sun", name))
+    };
+    let mut mask = 0u8;
+    for entry in days {
+        let (first, last) = match entry.split_once('-') {
+            Some((a, b)) => (index(a)?, index(b)?),
+            None => (index(entry)?, index(entry)?),
+        };
+        // A range like `sat-mon` wraps around the week.
+        let mut day = first;
+        loop {
+            mask |= 1 << day;
+            if day == last {
+                break;
+            }
+            day = (day + 1) % 7;
+        }
+    }
+    Ok(mask)
+}
+
+impl Schedule {
+    pub fn parse(settings: &ScheduleSettings) -> Result<Self, String> {
+        let windows = settings
+            .windows
+            .iter()
+            .map(|w| {
+                Ok(Window {
+                    days: parse_days(&w.days)?,
+                    start: parse_time(&w.start)?,
+                    end: parse_time(&w.end)?,
+                    state: w.state,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let zone = if settings.utc_offset.trim().is_empty() {
+            Zone::Local(local_offset)
+        } else {
+            Zone::Fixed(parse_offset(&settings.utc_offset)?)
+        };
+        Ok(Self {
+            zone,
+            default_state: settings.default_state,
+            windows,
+        })
+    }
+
+    /// Scheduled state at Unix time `now`.
+    pub fn state_at(&self, now: u64) -> ScheduleState {
+        let local = now as i64 + self.zone.offset_at(now);
+        let days = local.div_euclid(DAY);
+        // 1970-01-01 was a Thursday; 0 is Monday.
+        let weekday = (days + 3).rem_euclid(7);
+        let minute = local.rem_euclid(DAY) / 60;
+        self.windows
+            .iter()
+            .find(|w| w.contains(weekday, minute))
+            .map(|w| w.state)
+            .unwrap_or(self.default_state)
+    }
+
+    /// The next time the scheduled state changes, and the state it changes to.
+    pub fn next_change(&self, now: u64) -> Option<(u64, ScheduleState)> {
+        let current = self.state_at(now);
+        let first_minute = now - now % 60 + 60;
+        (0..LOOKAHEAD_MINUTES)
+            .map(|i| first_minute + i as u64 * 60)
+            .map(|t| (t, self.state_at(t)))
+            .find(|(_, state)| *state != current)
+    }
+}
+
+/// Schedule state of one tunnel as served by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleStatus {
+    pub state: ScheduleState,
+    pub next_state: Option<ScheduleState>,
+    /// Unix time of the next change, seconds.
+    pub next_change_at: Option<u64>,
+    /// A manual connect or disconnect holds until this time (the next change).
+    pub override_until: Option<u64>,
+}
+
+/// Connects and disconnects tunnels on the schedule. Acts only when the
+/// scheduled state changes, so a manual connect or disconnect holds until
+/// the next change.
+pub struct Scheduler {
+    schedule: Schedule,
+    tunnels: Arc<TunnelRegistry>,
+    ids: Vec<String>,
+    applied: Mutex<HashMap<String, ScheduleState>>,
+    overrides: Mutex<HashMap<String, u64>>,
+}
+
+impl Scheduler {
+    /// `None` when the schedule is disabled.
+    pub fn new(
+        settings: &ScheduleSettings,
+        tunnels: Arc<TunnelRegistry>,
+    ) -> Result<Option<Arc<Self>>, String> {
+        if !settings.enabled {
+            return Ok(None);
+        }
+        let schedule = Schedule::parse(settings)?;
+        let ids: Vec<String> = if settings.tunnels.is_empty() {
+            tunnels.iter().map(|(id, _)| id.to_string()).collect()
+        } else {
+            settings
+                .tunnels
+                .iter()
+                .map(|id| id.trim().to_string())
+                .collect()
+        };
+        if let Some(unknown) = ids.iter().find(|id| tunnels.get(id).is_none()) {
+            return Err(format!("schedule: unknown tunnel '{}'", unknown));
+        }
+        Ok(Some(Arc::new(Self {
+            schedule,
+            tunnels,
+            ids,
+            applied: Mutex::new(HashMap::new()),
+            overrides: Mutex::new(HashMap::new()),
+        })))
+    }
+
+    /// Whether the schedule decides when `id` runs.
+    pub fn drives(&self, id: &str) -> bool {
+        self.ids.iter().any(|i| i == id)
+    }
+
+    pub fn run_loop(&self) {
+        loop {
+            self.tick(unix_now());
+            std::thread::sleep(TICK);
+        }
+    }
+
+    fn tick(&self, now: u64) {
+        let state = self.schedule.state_at(now);
+        for id in &self.ids {
+            if self.applied.lock().unwrap().get(id) == Some(&state) {
+                continue;
+            }
+            self.applied.lock().unwrap().insert(id.clone(), state);
+            self.overrides.lock().unwrap().remove(id);
+            let Some(tunnel) = self.tunnels.get(id) else {
+                continue;
+            };
+            let msg = format!(
+                "[schedule] '{}' scheduled {}",
+                id,
+                match state {
+                    ScheduleState::Up => "up, connecting",
+                    ScheduleState::Down => "down, disconnecting",
+                }
+            );
+            log::info!("{}", msg);
+            logs::global_buffer().push(msg);
+            match state {
+                ScheduleState::Up => {
+                    if let Err(e) = tunnel.start() {
+                        let msg = format!("[schedule] connecting '{}' failed: {}", id, e);
+                        log::warn!("{}", msg);
+                        logs::global_buffer().push(msg);
+                    }
+                }
+                ScheduleState::Down => tunnel.stop(StopReason::Schedule),
+            }
+        }
+    }
+
+    /// Record a manual connect or disconnect of `id`; it holds until the
+    /// next scheduled change.
+    pub fn note_manual(&self, id: &str) {
+        if !self.drives(id) {
+            return;
+        }
+        if let Some((at, _)) = self.schedule.next_change(unix_now()) {
+            self.overrides.lock().unwrap().insert(id.to_string(), at);
+        }
+    }
+
+    pub fn status(&self, id: &str) -> Option<ScheduleStatus> {
+        if !self.drives(id) {
+            return None;
+        }
+        let now = unix_now();
+        let next = self.schedule.next_change(now);
+        Some(ScheduleStatus {
+            state: self.schedule.state_at(now),
+            next_state: next.map(|(_, state)| state),
+            next_change_at: next.map(|(at, _)| at),
+            override_until: self.overrides.lock().unwrap().get(id).copied(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ScheduleWindow;
+
+    #[test]
+    fn test_windows_and_next_change() {
+        let schedule = Schedule::parse(&ScheduleSettings {
+            enabled: true,
+            utc_offset: "+03:00".into(),
+            timezone: String::new(),
+            tunnels: Vec::new(),
+            default_state: ScheduleState::Down,
+            windows: vec![
+                ScheduleWindow {
+                    days: Vec::new(),
+                    start: "02:00".into(),
+                    end: "03:00".into(),
+                    state: ScheduleState::Down,
+                },
+                ScheduleWindow {
+                    days: vec!["mon-fri".into()],
+                    start: "22:00".into(),
+                    end: "06:00".into(),
+                    state: ScheduleState::Up,
+                },
+            ],
+        })
+        .unwrap();
+        // Sunday 2026-10-18 21:00 UTC is Monday 00:00 local: Sunday's
+        // window is not scheduled, so the default applies.
+        let sunday_21_utc = 1_792_357_200;
+        assert_eq!(schedule.state_at(sunday_21_utc), ScheduleState::Down);
+        // Monday 22:00 local starts the overnight window.
+        let monday_22_local = sunday_21_utc + 22 * 3600;
+        assert_eq!(schedule.state_at(monday_22_local), ScheduleState::Up);
+        // The earlier backup window wins at Tuesday 02:30 local.
+        assert_eq!(
+            schedule.state_at(monday_22_local + 4 * 3600 + 1800),
+            ScheduleState::Down
+        );
+        assert_eq!(
+            schedule.next_change(monday_22_local),
+            Some((monday_22_local + 4 * 3600, ScheduleState::Down))
+        );
+
+        // Daylight saving time: +01:00 until 2026-10-25 01:00 UTC, +00:00 after.
+        fn dublin(now: u64) -> i64 {
+            if now < 1_792_890_000 {
+                3600
+            } else {
+                0
+            }
+        }
+        let mut schedule = schedule;
+        schedule.zone = Zone::Local(dublin);
+        // Monday 2026-10-19 22:30 local is 21:30 UTC before the change...
+        assert_eq!(schedule.state_at(1_792_445_400), ScheduleState::Up);
+        // ...and Monday 2026-10-26 22:30 local is 22:30 UTC after it.
+        assert_eq!(schedule.state_at(1_793_053_800), ScheduleState::Up);
+        assert_eq!(schedule.state_at(1_793_053_800 - 3600), ScheduleState::Down);
+
+        assert_eq!(parse_days(&["sat-mon".into()]), Ok(0b110_0001));
+        assert!(parse_offset("3").is_err());
+        assert!(parse_time("24:00").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use crate::config::SessionSettings;
+use crate::logger::RotatingFile;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::Mutex;
+
+/// Why a client session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// Disconnect or restart requested through the API.
+    User,
+    /// Restarted by the watchdog recovery ladder.
+    Watchdog,
+    /// The client exited on its own.
+    Crash,
+    /// The wrapper itself is shutting down.
+    Shutdown,
+    /// The data quota ran out.
+    Quota,
+    /// A schedule window turned the tunnel off.
+    Schedule,
+    /// Paused through the API.
+    Pause,
+    /// Restarted to apply refreshed route lists.
+    RouteLists,
+}
+
+/// One finished client session, as stored in the history file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    /// Unix times, seconds.
+    pub start: u64,
+    pub end: u64,
+    pub duration_secs: u64,
+    pub endpoint: String,
+    pub address: String,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub reason: StopReason,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+impl SessionRecord {
+    pub fn set_exit(&mut self, status: &ExitStatus) {
+        use std::os::unix::process::ExitStatusExt;
+        self.exit_code = status.code();
+        self.signal = status.signal();
+    }
+}
+
+/// Session history of one tunnel, kept in a rotated JSON lines file.
+pub struct SessionLog {
+    path: PathBuf,
+    rotate_keep: usize,
+    file: Mutex<Option<RotatingFile>>,
+}
+
+impl SessionLog {
+    /// `None` when the history is disabled or its file cannot be opened.
+    pub fn open(settings: &SessionSettings) -> Option<Self> {
+        if settings.path.trim().is_empty() {
+            return None;
+        }
+        let path = PathBuf::from(settings.path.trim());
+        match RotatingFile::new(path.clone(), settings.rotate_size, settings.rotate_keep) {
+            Ok(file) => Some(Self {
+                path,
+                rotate_keep: settings.rotate_keep,
+                file: Mutex::new(Some(file)),
+            }),
+            Err(e) => {
+                log::warn!("[sessions] history disabled: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn append(&self, record: &SessionRecord) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("[sessions] cannot serialize session: {}", e);
+                return;
+            }
+        };
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            if let Err(e) = file.write_line(&line) {
+                log::warn!("[sessions] {}", e);
+            }
+        }
+    }
+
+    /// The last `count` sessions across the current and rotated files,
+    /// oldest first. Unreadable lines are skipped.
+    pub fn recent(&self, count: usize) -> Vec<SessionRecord> {
+        // Hold the writer lock so a rotation cannot move files mid-read.
+        let _guard = self.file.lock().unwrap();
+        let mut records = Vec::new();
+        let archives = (1..=self.rotate_keep)
+            .rev()
+            .map(|idx| PathBuf::from(format!("{}.{}", self.path.display(), idx)));
+        for path in archives.chain(std::iter::once(self.path.clone())) {
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            records.extend(
+                content
+                    .lines()
+                    .filter_map(|line| serde_json::from_str::<SessionRecord>(line).ok()),
+            );
+        }
+        let skip = records.len().saturating_sub(count);
+        records.split_off(skip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_rotate_and_read_back() {
+        let dir = std::env::temp_dir().join(format!("tt-sessions-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let log = SessionLog::open(&SessionSettings {
+            path: dir.join("sessions.jsonl").to_string_lossy().into_owned(),
+            rotate_size: 1024,
+            rotate_keep: 2,
+        })
+        .unwrap();
+        let record = |start: u64| SessionRecord {
+            start,
+            end: start + 60,
+            duration_secs: 60,
+            endpoint: "vpn.example.com".into(),
+            address: "203.0.113.10:443".into(),
+            exit_code: None,
+            signal: Some(15),
+            reason: StopReason::Watchdog,
+            rx_bytes: 1 << 20,
+            tx_bytes: 1 << 16,
+        };
+        for start in 0..40 {
+            log.append(&record(start));
+        }
+        let recent = log.recent(5);
+        assert_eq!(
+            recent.iter().map(|r| r.start).collect::<Vec<_>>(),
+            vec![35, 36, 37, 38, 39]
+        );
+        // Rotation keeps the newest records in `.1`/`.2` and drops the rest.
+        let all = log.recent(usize::MAX);
+        assert!(all.len() < 40 && all.len() > 5);
+        assert_eq!(all.last().unwrap().start, 39);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Run state last requested for a tunnel through the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum DesiredState {
+    Connected,
+    Disconnected,
+    /// Paused until the given Unix time, seconds.
+    Paused {
+        until: u64,
+    },
+}
+
+/// Desired state of every tunnel, saved to a small JSON file on each change.
+pub struct StateFile {
+    path: String,
+    states: Mutex<BTreeMap<String, DesiredState>>,
+}
+
+impl StateFile {
+    /// Load `path`; a missing or unreadable file means nothing was requested.
+    pub fn load(path: &str) -> Self {
+        let states = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|content| match serde_json::from_str(&content) {
+                Ok(states) => Some(states),
+                Err(e) => {
+                    log::warn!("[state] ignoring {}: {}", path, e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            path: path.to_string(),
+            states: Mutex::new(states),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<DesiredState> {
+        self.states.lock().unwrap().get(id).copied()
+    }
+
+    pub fn set(&self, id: &str, state: DesiredState) {
+        let mut states = self.states.lock().unwrap();
+        if states.get(id) == Some(&state) {
+            return;
+        }
+        states.insert(id.to_string(), state);
+        if self.path.is_empty() {
+            return;
+        }
+        let content = match serde_json::to_string_pretty(&*states) {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("[state] cannot serialize: {}", e);
+                return;
+            }
+        };
+        let path = std::path::Path::new(&self.path);
+        if let Err(e) = crate::util::atomic_write(path, content) {
+            log::warn!("[state] cannot save {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("tt-state-{}.json", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+
+        let file = StateFile::load(&path);
+        assert_eq!(file.get("main"), None);
+        file.set(
+            "main",
+            DesiredState::Paused {
+                until: 1_792_357_200,
+            },
+        );
+        file.set("work", DesiredState::Disconnected);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains(r#""state": "paused""#));
+        let reloaded = StateFile::load(&path);
+        assert_eq!(
+            reloaded.get("main"),
+            Some(DesiredState::Paused {
+                until: 1_792_357_200
+            })
+        );
+        assert_eq!(reloaded.get("work"), Some(DesiredState::Disconnected));
+        let _ = std::fs::remove_file(&path);
+    }
+}
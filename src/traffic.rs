@@ -0,0 +1,339 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// (bucket width in seconds, buckets kept): 5 minutes of seconds, 24 hours
+/// of minutes, 7 days of hours.
+const SECOND_SERIES: (u64, usize) = (1, 300);
+const MINUTE_SERIES: (u64, usize) = (60, 24 * 60);
+const HOUR_SERIES: (u64, usize) = (3600, 7 * 24);
+
+/// Interface counters from `/sys/class/net/<dev>/statistics`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Counters {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+}
+
+impl Counters {
+    fn read(dev: &str) -> Option<Self> {
+        let stat = |name: &str| -> Option<u64> {
+            std::fs::read_to_string(format!("/sys/class/net/{}/statistics/{}", dev, name))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()
+        };
+        Some(Self {
+            rx_bytes: stat("rx_bytes")?,
+            tx_bytes: stat("tx_bytes")?,
+            rx_packets: stat("rx_packets")?,
+            tx_packets: stat("tx_packets")?,
+            rx_errors: stat("rx_errors")?,
+            tx_errors: stat("tx_errors")?,
+        })
+    }
+
+    /// Read `dev`, telling a missing interface apart from a failed read.
+    fn reading(dev: &str) -> Reading {
+        match Self::read(dev) {
+            Some(counters) => Reading::Counters(counters),
+            None if !std::path::Path::new(&format!("/sys/class/net/{}", dev)).exists() => {
+                Reading::Missing
+            }
+            None => Reading::Failed,
+        }
+    }
+
+    fn fields(&self) -> [u64; 6] {
+        [
+            self.rx_bytes,
+            self.tx_bytes,
+            self.rx_packets,
+            self.tx_packets,
+            self.rx_errors,
+            self.tx_errors,
+        ]
+    }
+
+    /// Growth since `prev`. A counter that went backwards means the
+    /// interface was recreated, so everything counts from zero.
+    fn delta_since(&self, prev: &Counters) -> Counters {
+        let reset = self.fields().iter().zip(prev.fields()).any(|(c, p)| *c < p);
+        if reset {
+            return *self;
+        }
+        Counters {
+            rx_bytes: self.rx_bytes - prev.rx_bytes,
+            tx_bytes: self.tx_bytes - prev.tx_bytes,
+            rx_packets: self.rx_packets - prev.rx_packets,
+            tx_packets: self.tx_packets - prev.tx_packets,
+            rx_errors: self.rx_errors - prev.rx_errors,
+            tx_errors: self.tx_errors - prev.tx_errors,
+        }
+    }
+
+    fn add(&mut self, other: &Counters) {
+        self.rx_bytes += other.rx_bytes;
+        self.tx_bytes += other.tx_bytes;
+        self.rx_packets += other.rx_packets;
+        self.tx_packets += other.tx_packets;
+        self.rx_errors += other.rx_errors;
+        self.tx_errors += other.tx_errors;
+    }
+}
+
+/// One read of the interface counters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reading {
+    /// The interface does not exist.
+    Missing,
+    /// The interface exists but its counters could not be read.
+    Failed,
+    Counters(Counters),
+}
+
+/// Bytes moved during one bucket.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrafficBucket {
+    /// Unix time of the bucket start, seconds.
+    pub time: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+struct Series {
+    width: u64,
+    max: usize,
+    buckets: VecDeque<TrafficBucket>,
+}
+
+impl Series {
+    fn new((width, max): (u64, usize)) -> Self {
+        Self {
+            width,
+            max,
+            buckets: VecDeque::with_capacity(max.min(300)),
+        }
+    }
+
+    fn add(&mut self, now: u64, delta: &Counters) {
+        let time = now - now % self.width;
+        match self.buckets.back_mut() {
+            Some(b) if b.time == time => {
+                b.rx_bytes += delta.rx_bytes;
+                b.tx_bytes += delta.tx_bytes;
+            }
+            _ => {
+                self.buckets.push_back(TrafficBucket {
+                    time,
+                    rx_bytes: delta.rx_bytes,
+                    tx_bytes: delta.tx_bytes,
+                });
+                while self.buckets.len() > self.max {
+                    self.buckets.pop_front();
+                }
+            }
+        }
+    }
+}
+
+/// Bucket width of `TrafficMeter::history`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Second,
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "second" => Some(Self::Second),
+            "minute" => Some(Self::Minute),
+            "hour" => Some(Self::Hour),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Second => "second",
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+        }
+    }
+}
+
+/// Counters and rates served with the tunnel status.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TrafficSnapshot {
+    /// Since the current client session started.
+    pub session: Counters,
+    /// Since the wrapper started, across reconnects.
+    pub total: Counters,
+    pub rx_rate_bps: u64,
+    pub tx_rate_bps: u64,
+}
+
+struct MeterState {
+    last_raw: Option<Counters>,
+    /// The interface was seen missing since `last_raw` was cleared, so the
+    /// next reading comes from a new interface that started at zero.
+    seen_missing: bool,
+    last_sample: Option<Instant>,
+    session: Counters,
+    total: Counters,
+    rx_rate_bps: u64,
+    tx_rate_bps: u64,
+    seconds: Series,
+    minutes: Series,
+    hours: Series,
+}
+
+/// Traffic accounting for one tunnel interface.
+pub struct TrafficMeter {
+    state: Mutex<MeterState>,
+}
+
+impl TrafficMeter {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(MeterState {
+                last_raw: None,
+                seen_missing: false,
+                last_sample: None,
+                session: Counters::default(),
+                total: Counters::default(),
+                rx_rate_bps: 0,
+                tx_rate_bps: 0,
+                seconds: Series::new(SECOND_SERIES),
+                minutes: Series::new(MINUTE_SERIES),
+                hours: Series::new(HOUR_SERIES),
+            }),
+        }
+    }
+
+    /// Read the counters of `dev`; call every `SAMPLE_INTERVAL`.
+    pub fn sample(&self, dev: &str) {
+        let reading = Counters::reading(dev);
+        let now = crate::util::unix_now();
+        self.state
+            .lock()
+            .unwrap()
+            .apply(reading, now, Instant::now());
+    }
+
+    /// Start counting a new client session.
+    pub fn start_session(&self) {
+        self.state.lock().unwrap().session = Counters::default();
+    }
+
+    pub fn snapshot(&self) -> TrafficSnapshot {
+        let state = self.state.lock().unwrap();
+        TrafficSnapshot {
+            session: state.session,
+            total: state.total,
+            rx_rate_bps: state.rx_rate_bps,
+            tx_rate_bps: state.tx_rate_bps,
+        }
+    }
+
+    pub fn history(&self, resolution: Resolution) -> Vec<TrafficBucket> {
+        let state = self.state.lock().unwrap();
+        let series = match resolution {
+            Resolution::Second => &state.seconds,
+            Resolution::Minute => &state.minutes,
+            Resolution::Hour => &state.hours,
+        };
+        series.buckets.iter().cloned().collect()
+    }
+}
+
+impl MeterState {
+    /// Fold one reading into totals, rates and history.
+    fn apply(&mut self, reading: Reading, now: u64, at: Instant) {
+        let raw = match reading {
+            // Keep the previous reading; the next good one covers the gap.
+            Reading::Failed => return,
+            Reading::Missing => {
+                self.last_sample = Some(at);
+                self.last_raw = None;
+                self.seen_missing = true;
+                self.rx_rate_bps = 0;
+                self.tx_rate_bps = 0;
+                return;
+            }
+            Reading::Counters(raw) => raw,
+        };
+        let elapsed = self.last_sample.map(|t| at.duration_since(t));
+        self.last_sample = Some(at);
+        let delta = match &self.last_raw {
+            Some(prev) => raw.delta_since(prev),
+            // A new interface starts at zero, so its first reading is all new.
+            None if self.seen_missing => raw,
+            // First reading after start: the interface may have carried
+            // traffic before we were watching, so it is only a baseline.
+            None => Counters::default(),
+        };
+        self.last_raw = Some(raw);
+        self.seen_missing = false;
+        self.session.add(&delta);
+        self.total.add(&delta);
+        for series in [&mut self.seconds, &mut self.minutes, &mut self.hours] {
+            series.add(now, &delta);
+        }
+        let secs = elapsed.map(|e| e.as_secs_f64()).unwrap_or(0.0);
+        if secs > 0.0 {
+            self.rx_rate_bps = (delta.rx_bytes as f64 * 8.0 / secs) as u64;
+            self.tx_rate_bps = (delta.tx_bytes as f64 * 8.0 / secs) as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(rx: u64, tx: u64) -> Counters {
+        Counters {
+            rx_bytes: rx,
+            tx_bytes: tx,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_counter_reset_and_buckets() {
+        let meter = TrafficMeter::new();
+        let mut state = meter.state.lock().unwrap();
+        let t = Instant::now();
+        let now = 1_760_000_000;
+        let counters = |rx, tx| Reading::Counters(bytes(rx, tx));
+        // The first reading is a baseline: the interface predates the meter.
+        state.apply(counters(1000, 500), now, t);
+        assert_eq!(state.total, bytes(0, 0));
+        state.apply(counters(3000, 1500), now + 1, t + Duration::from_secs(1));
+        assert_eq!(state.rx_rate_bps, 16_000);
+        // A failed read changes nothing.
+        state.apply(Reading::Failed, now + 2, t + Duration::from_secs(2));
+        state.apply(counters(3200, 1600), now + 3, t + Duration::from_secs(3));
+        assert_eq!(state.total, bytes(2200, 1100));
+        // Interface recreated: counters restart below the previous reading.
+        state.apply(counters(200, 100), now + 4, t + Duration::from_secs(4));
+        assert_eq!(state.total, bytes(2400, 1200));
+        // Interface gone, then back with fresh counters.
+        state.apply(Reading::Missing, now + 5, t + Duration::from_secs(5));
+        state.apply(counters(50, 10), now + 6, t + Duration::from_secs(6));
+        assert_eq!(state.total, bytes(2450, 1210));
+        assert_eq!(state.seconds.buckets.len(), 5);
+        assert_eq!(state.hours.buckets.len(), 1);
+        assert_eq!(state.hours.buckets[0].rx_bytes, 2450);
+    }
+}
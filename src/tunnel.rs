@@ -1,13 +1,33 @@
-use crate::config::{generate_client_toml, TunnelSettings};
+use crate::config::{
+    generate_client_toml, DnsSettings, InstanceSettings, LadderRung, OrphanPolicy, ProbeSettings,
+    QuotaAction, RecoveryAction, RoutingSettings, TunnelSettings,
+};
+use crate::dns;
+use crate::events::{Event, EventHistory};
+use crate::journal::Journal;
+use crate::killswitch::KillSwitch;
 use crate::logs;
-use crate::routing;
-use std::process::{Child, Command, Stdio};
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crate::probes::{self, ProbeRound};
+use crate::process::{self, ClientProcess, Poll};
+use crate::quality::QualityMonitor;
+use crate::quota::{Quota, QuotaEvent, QuotaStatus};
+use crate::routelists::TunnelRouteLists;
+use crate::routing::{self, InterfaceNames, RouteScope, TeardownLevel};
+use crate::sessions::{SessionLog, SessionRecord, StopReason};
+use crate::traffic::{Resolution, TrafficBucket, TrafficMeter, TrafficSnapshot};
+use crate::util::unix_now;
+use std::net::IpAddr;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 const CLIENT_BIN: &str = "/opt/bin/trusttunnel_client";
 const CLIENT_TOML: &str = "/opt/etc/trusttunnel/trusttunnel_client.toml";
+const CLIENT_PIDFILE: &str = "/opt/var/run/trusttunnel_client.pid";
+const MAX_EVENTS: usize = 200;
+/// Per-server limit for re-resolving endpoint hostnames over the WAN.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone, Default)]
 pub struct TunnelStatus {
@@ -15,31 +35,134 @@ pub struct TunnelStatus {
     pub uptime_seconds: u64,
     pub last_error: String,
     pub pid: Option<u32>,
+    pub server_ips: Vec<IpAddr>,
 }
 
 pub struct TunnelManager {
     settings: Mutex<TunnelSettings>,
     status: Mutex<TunnelStatus>,
-    child: Mutex<Option<Child>>,
+    child: Mutex<Option<ClientProcess>>,
     running: AtomicBool,
     should_stop: AtomicBool,
     connect_time: Mutex<Option<Instant>>,
     routing_enabled: bool,
     routing_active: Arc<AtomicBool>,
     routing_setup_in_progress: Arc<AtomicBool>,
+    /// Set once `recover_previous_run` has run; connecting waits for it.
+    recovered: AtomicBool,
     // watchdog
     watchdog_enabled: bool,
     watchdog_interval: Duration,
-    watchdog_max_failures: u32,
-    watchdog_check_url: String,
+    watchdog_ladder: Vec<LadderRung>,
+    /// Index of the next ladder rung; back to 0 after a healthy check.
+    ladder_rung: AtomicUsize,
+    watchdog_probes: Vec<ProbeSettings>,
+    watchdog_quorum: u32,
     watchdog_check_timeout: Duration,
+    last_probe_round: Mutex<Option<ProbeRound>>,
+    watchdog_wan_check: bool,
+    /// Set while both the tunnel and WAN probes fail; respawns are held
+    /// until WAN answers again.
+    waiting_for_wan: AtomicBool,
+    last_wan_round: Mutex<Option<ProbeRound>>,
     watchdog_failures: AtomicU32,
     last_watchdog_check: Mutex<Instant>,
     last_wan_interface: Arc<Mutex<String>>,
+    // server host routes
+    server_ips: Arc<Mutex<Vec<IpAddr>>>,
+    resolve_interval: Duration,
+    resolve_servers: Vec<String>,
+    last_resolve: Mutex<Instant>,
+    route_lists: TunnelRouteLists,
+    /// `route_lists` generation the running client was configured with.
+    route_lists_applied: AtomicU64,
+    journal: Arc<Mutex<Journal>>,
+    names: InterfaceNames,
+    client_toml: String,
+    client_pidfile: String,
+    default_route: bool,
+    killswitch: Option<KillSwitch>,
+    /// DNS servers to bind to the tunnel once routing is up.
+    dns: Option<DnsSettings>,
+    events: EventHistory,
+    quality: Option<QualityMonitor>,
+    traffic: TrafficMeter,
+    sessions: Option<SessionLog>,
+    /// Session of the running client, written to `sessions` when it ends.
+    current_session: Mutex<Option<SessionRecord>>,
+    quota: Option<Quota>,
+    /// Unix time a paused tunnel reconnects at.
+    paused_until: Mutex<Option<u64>>,
+}
+
+/// Configured ladder, or the legacy single step: restart the client after
+/// `watchdog_failures` failed checks.
+fn ladder_from_settings(routing: &RoutingSettings) -> Vec<LadderRung> {
+    if !routing.watchdog_ladder.is_empty() {
+        return routing.watchdog_ladder.clone();
+    }
+    vec![LadderRung {
+        action: RecoveryAction::RestartClient,
+        failures: routing.watchdog_failures,
+    }]
+}
+
+/// Client TOML path for an instance; instances other than 0 get a suffix.
+fn client_toml_path(instance: &InstanceSettings) -> String {
+    if !instance.client_toml.trim().is_empty() {
+        instance.client_toml.trim().to_string()
+    } else if instance.index == 0 {
+        CLIENT_TOML.to_string()
+    } else {
+        CLIENT_TOML.replace(".toml", &format!("{}.toml", instance.index))
+    }
+}
+
+/// Client PID file for an instance, suffixed like `client_toml_path`.
+fn client_pidfile_path(instance: &InstanceSettings) -> String {
+    if !instance.client_pidfile.trim().is_empty() {
+        instance.client_pidfile.trim().to_string()
+    } else if instance.index == 0 {
+        CLIENT_PIDFILE.to_string()
+    } else {
+        CLIENT_PIDFILE.replace(".pid", &format!("{}.pid", instance.index))
+    }
+}
+
+/// Resolve endpoint addresses straight from `servers` out of the WAN, so a
+/// tunnel that is down, or a DNS proxy pointing into it, cannot answer. The
+/// WAN is the current upstream default route, else `last_wan`; with neither
+/// known, the system resolver is the only option.
+fn resolve_over_wan(
+    addresses: &[String],
+    servers: &[String],
+    last_wan: &str,
+) -> (Vec<IpAddr>, Vec<String>) {
+    match routing::current_wan_interface()
+        .or_else(|| Some(last_wan.to_string()).filter(|w| !w.is_empty()))
+    {
+        Some(wan) => routing::resolve_server_ips_via(addresses, servers, &wan, RESOLVE_TIMEOUT),
+        None => routing::resolve_server_ips(addresses),
+    }
 }
 
 impl TunnelManager {
-    pub fn new(settings: TunnelSettings, routing: &crate::config::RoutingSettings) -> Arc<Self> {
+    pub fn new(
+        settings: TunnelSettings,
+        routing: &RoutingSettings,
+        instance: &InstanceSettings,
+        default_route: bool,
+        killswitch: Option<KillSwitch>,
+        dns: Option<DnsSettings>,
+        route_lists: TunnelRouteLists,
+    ) -> Arc<Self> {
+        let journal = Journal::load(&routing.journal_path);
+        if !journal.is_empty() {
+            log::info!(
+                "[journal] {} routing changes recorded by a previous run",
+                journal.entries().len()
+            );
+        }
         Arc::new(Self {
             settings: Mutex::new(settings),
             status: Mutex::new(TunnelStatus::default()),
@@ -50,14 +173,44 @@ impl TunnelManager {
             routing_enabled: routing.enabled,
             routing_active: Arc::new(AtomicBool::new(false)),
             routing_setup_in_progress: Arc::new(AtomicBool::new(false)),
+            recovered: AtomicBool::new(false),
             watchdog_enabled: routing.watchdog_enabled,
             watchdog_interval: Duration::from_secs(routing.watchdog_interval),
-            watchdog_max_failures: routing.watchdog_failures,
-            watchdog_check_url: routing.watchdog_check_url.clone(),
+            watchdog_ladder: ladder_from_settings(routing),
+            ladder_rung: AtomicUsize::new(0),
+            watchdog_probes: probes::from_settings(routing),
+            watchdog_quorum: routing.watchdog_quorum,
             watchdog_check_timeout: Duration::from_secs(routing.watchdog_check_timeout),
+            last_probe_round: Mutex::new(None),
+            watchdog_wan_check: routing.watchdog_wan_check,
+            waiting_for_wan: AtomicBool::new(false),
+            last_wan_round: Mutex::new(None),
             watchdog_failures: AtomicU32::new(0),
             last_watchdog_check: Mutex::new(Instant::now()),
             last_wan_interface: Arc::new(Mutex::new(String::new())),
+            server_ips: Arc::new(Mutex::new(Vec::new())),
+            resolve_interval: Duration::from_secs(routing.resolve_interval.max(30)),
+            resolve_servers: routing.resolve_servers.clone(),
+            last_resolve: Mutex::new(Instant::now()),
+            route_lists,
+            route_lists_applied: AtomicU64::new(0),
+            journal: Arc::new(Mutex::new(journal)),
+            names: InterfaceNames::from_settings(instance),
+            client_toml: client_toml_path(instance),
+            client_pidfile: client_pidfile_path(instance),
+            default_route,
+            killswitch,
+            dns,
+            events: EventHistory::new(MAX_EVENTS),
+            quality: routing
+                .quality
+                .enabled
+                .then(|| QualityMonitor::new(&routing.quality)),
+            traffic: TrafficMeter::new(),
+            sessions: SessionLog::open(&routing.sessions),
+            current_session: Mutex::new(None),
+            quota: Quota::new(&routing.quota),
+            paused_until: Mutex::new(None),
         })
     }
 
@@ -68,6 +221,7 @@ impl TunnelManager {
                 st.uptime_seconds = t.elapsed().as_secs();
             }
         }
+        st.server_ips = self.server_ips.lock().unwrap().clone();
         st
     }
 
@@ -75,15 +229,79 @@ impl TunnelManager {
         *self.settings.lock().unwrap() = new;
     }
 
+    pub fn interface_names(&self) -> &InterfaceNames {
+        &self.names
+    }
+
+    pub fn owns_default_route(&self) -> bool {
+        self.default_route
+    }
+
+    pub fn killswitch_engaged(&self) -> bool {
+        self.killswitch.as_ref().is_some_and(|ks| ks.is_engaged())
+    }
+
+    /// Engage the kill switch while the tunnel should be up but is not
+    /// connected; release it otherwise.
+    fn sync_killswitch(&self) {
+        let Some(ks) = &self.killswitch else {
+            return;
+        };
+        let wanted_up =
+            self.running.load(Ordering::SeqCst) && !self.should_stop.load(Ordering::SeqCst);
+        let connected = if self.routing_enabled {
+            self.routing_active.load(Ordering::SeqCst)
+        } else {
+            self.status.lock().unwrap().connected
+        };
+        if wanted_up && !connected {
+            let ips = self.server_ips.lock().unwrap().clone();
+            let ports = self.settings.lock().unwrap().killswitch_allow_ports.clone();
+            ks.engage(&ips, &ports);
+        } else if ks.is_engaged() {
+            ks.release();
+        }
+    }
+
+    /// Default route for the owner; otherwise the effective included routes,
+    /// minus catch-all prefixes that would take over the default route.
+    fn route_scope(&self) -> RouteScope {
+        if self.default_route {
+            return RouteScope::Default;
+        }
+        let mut settings = self.settings.lock().unwrap().clone();
+        self.route_lists.apply(&mut settings);
+        let prefixes = settings
+            .included_routes
+            .into_iter()
+            .filter(|p| {
+                let catch_all = matches!(p.as_str(), "0.0.0.0/0" | "::/0" | "2000::/3");
+                if catch_all {
+                    log::warn!(
+                        "[routing] {} does not own the default route, skipping {}",
+                        self.names.ndm,
+                        p
+                    );
+                }
+                !catch_all
+            })
+            .collect();
+        RouteScope::Prefixes(prefixes)
+    }
+
     /// Write the TOML config to disk so trusttunnel_client can read it.
     fn write_toml_config(&self) -> Result<(), String> {
-        let settings = self.settings.lock().unwrap().clone();
-        let toml_content = generate_client_toml(&settings);
+        let mut settings = self.settings.lock().unwrap().clone();
+        self.route_lists_applied
+            .store(self.route_lists.generation(), Ordering::SeqCst);
+        self.route_lists.apply(&mut settings);
+        let toml_content = generate_client_toml(&settings, &self.names.tun);
 
-        if let Some(parent) = std::path::Path::new(CLIENT_TOML).parent() {
+        if let Some(parent) = std::path::Path::new(&self.client_toml).parent() {
             std::fs::create_dir_all(parent).map_err(|e| format!("mkdir failed: {}", e))?;
         }
-        std::fs::write(CLIENT_TOML, toml_content).map_err(|e| format!("write toml failed: {}", e))
+        std::fs::write(&self.client_toml, toml_content)
+            .map_err(|e| format!("write toml failed: {}", e))
     }
 
     fn spawn_process(&self) -> Result<(), String> {
@@ -93,17 +311,23 @@ impl TunnelManager {
 
         let child = Command::new(CLIENT_BIN)
             .arg("--config")
-            .arg(CLIENT_TOML)
+            .arg(&self.client_toml)
             .arg("--loglevel")
             .arg(&loglevel)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
+            // Nothing reads the client's output, and a pipe would break once
+            // the client outlives this wrapper and is adopted by the next run.
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
             .spawn()
             .map_err(|e| format!("Failed to spawn {}: {}", CLIENT_BIN, e))?;
 
         let pid = child.id();
-        *self.child.lock().unwrap() = Some(child);
+        *self.child.lock().unwrap() = Some(ClientProcess::Spawned(child));
+        process::write_pidfile(&self.client_pidfile, pid);
         *self.connect_time.lock().unwrap() = Some(Instant::now());
+        self.traffic.start_session();
+        self.begin_session();
 
         {
             let mut st = self.status.lock().unwrap();
@@ -117,7 +341,58 @@ impl TunnelManager {
         Ok(())
     }
 
+    fn begin_session(&self) {
+        let settings = self.settings.lock().unwrap();
+        *self.current_session.lock().unwrap() = Some(SessionRecord {
+            start: unix_now(),
+            end: 0,
+            duration_secs: 0,
+            endpoint: settings.hostname.clone(),
+            address: settings.addresses.first().cloned().unwrap_or_default(),
+            exit_code: None,
+            signal: None,
+            reason: StopReason::Crash,
+            rx_bytes: 0,
+            tx_bytes: 0,
+        });
+    }
+
+    /// Close the current session and append it to the history.
+    fn end_session(&self, reason: StopReason, exit: Option<&ExitStatus>) {
+        let Some(mut record) = self.current_session.lock().unwrap().take() else {
+            return;
+        };
+        let traffic = self.traffic.snapshot().session;
+        record.end = unix_now();
+        record.duration_secs = record.end.saturating_sub(record.start);
+        record.reason = reason;
+        record.rx_bytes = traffic.rx_bytes;
+        record.tx_bytes = traffic.tx_bytes;
+        if let Some(status) = exit {
+            record.set_exit(status);
+        }
+        if let Some(sessions) = &self.sessions {
+            sessions.append(&record);
+        }
+    }
+
+    /// Recorded sessions, oldest first; `None` when the history is disabled.
+    pub fn sessions(&self, count: usize) -> Option<Vec<SessionRecord>> {
+        self.sessions.as_ref().map(|s| s.recent(count))
+    }
+
     pub fn start(&self) -> Result<(), String> {
+        if !self.recovered.load(Ordering::SeqCst) {
+            return Err("Still cleaning up after the previous run; try again shortly".into());
+        }
+        let exhausted = |action| self.quota.as_ref().filter(|q| q.exhausted_with(action));
+        if let Some(quota) = exhausted(QuotaAction::Disconnect) {
+            let st = quota.status();
+            return Err(format!(
+                "Data quota exhausted ({} of {} bytes used); reset it or connect with override_quota",
+                st.used_bytes, st.limit_bytes
+            ));
+        }
         if self.running.load(Ordering::SeqCst) {
             return Ok(());
         }
@@ -127,6 +402,25 @@ impl TunnelManager {
             return Err("Endpoint hostname and addresses are required".into());
         }
         drop(settings);
+        // Only a start that gets past the checks ends a pause.
+        *self.paused_until.lock().unwrap() = None;
+
+        // Block mode: come up held, as if the quota had run out while
+        // connected, so the monitor connects once the period resets.
+        if let Some(quota) = exhausted(QuotaAction::Block) {
+            let st = quota.status();
+            let msg = format!(
+                "[quota] data quota exhausted ({} of {} bytes used), holding the tunnel down until the period ends",
+                st.used_bytes, st.limit_bytes
+            );
+            log::warn!("{}", msg);
+            logs::global_buffer().push(msg.clone());
+            self.status.lock().unwrap().last_error = msg;
+            self.should_stop.store(false, Ordering::SeqCst);
+            self.running.store(true, Ordering::SeqCst);
+            self.sync_killswitch();
+            return Ok(());
+        }
 
         self.should_stop.store(false, Ordering::SeqCst);
         if let Err(e) = self.spawn_process() {
@@ -135,6 +429,7 @@ impl TunnelManager {
             return Err(e);
         }
         self.running.store(true, Ordering::SeqCst);
+        self.sync_killswitch();
         self.spawn_routing_setup();
         Ok(())
     }
@@ -155,15 +450,44 @@ impl TunnelManager {
         let flag = self.routing_active.clone();
         let wan_ref = self.last_wan_interface.clone();
         let in_progress = self.routing_setup_in_progress.clone();
+        let journal = self.journal.clone();
+        let names = self.names.clone();
+        let scope = self.route_scope();
+        let ips_ref = self.server_ips.clone();
+        let resolve_servers = self.resolve_servers.clone();
+        let dns_settings = self.dns.clone();
 
         let spawn_result = std::thread::Builder::new()
             .name("routing-setup".into())
-            .spawn(move || match routing::setup_routing(&addresses) {
-                Ok(wan) => {
-                    flag.store(true, Ordering::SeqCst);
-                    *wan_ref.lock().unwrap() = wan;
+            .spawn(move || {
+                let last_wan = wan_ref.lock().unwrap().clone();
+                let (ips, errors) = resolve_over_wan(&addresses, &resolve_servers, &last_wan);
+                for e in &errors {
+                    log::warn!("[routing] cannot resolve server address {}", e);
+                }
+                // A failed lookup keeps the last known IPs, so the host
+                // routes still keep the client's own traffic off the tunnel.
+                let ips = {
+                    let mut known = ips_ref.lock().unwrap();
+                    if errors.is_empty() || known.is_empty() {
+                        *known = ips;
+                    }
+                    known.clone()
+                };
+                match routing::setup_routing(&names, &scope, &ips, &journal) {
+                    Ok(wan) => {
+                        flag.store(true, Ordering::SeqCst);
+                        *wan_ref.lock().unwrap() = wan;
+                        if let Some(dns_settings) = &dns_settings {
+                            if let Err(e) = dns::apply(&names, dns_settings, &journal) {
+                                let msg = format!("[dns] setup failed: {}", e);
+                                log::warn!("{}", msg);
+                                logs::global_buffer().push(msg);
+                            }
+                        }
+                    }
+                    Err(e) => log::error!("[routing] setup failed: {}", e),
                 }
-                Err(e) => log::error!("[routing] setup failed: {}", e),
             });
         match spawn_result {
             Ok(handle) => {
@@ -179,48 +503,327 @@ impl TunnelManager {
         }
     }
 
-    fn teardown_if_active(&self) {
-        if self.routing_active.swap(false, Ordering::SeqCst) {
-            let addresses = self.settings.lock().unwrap().addresses.clone();
-            routing::teardown_routing(&addresses);
+    fn teardown_if_active(&self, level: TeardownLevel) {
+        let was_active = self.routing_active.swap(false, Ordering::SeqCst);
+        // A full teardown also cleans up after a setup that failed half-way
+        // or never reported success, as long as the journal has entries.
+        let pending = level == TeardownLevel::Full
+            && self.routing_enabled
+            && !self.journal.lock().unwrap().is_empty();
+        if was_active || pending {
+            routing::teardown_routing(&self.names, &self.journal, level);
         }
     }
 
-    pub fn stop(&self) {
-        self.should_stop.store(true, Ordering::SeqCst);
-        self.running.store(false, Ordering::SeqCst);
-        self.kill_child();
-        self.teardown_if_active();
+    /// Adopt or stop a client left running by a previous run, otherwise undo
+    /// the routing it left behind. Call once at startup, after the boot gate
+    /// so NDM answers the undo; `start` is refused until then.
+    pub fn recover_previous_run(&self, policy: OrphanPolicy) {
+        if !self.reclaim_orphan(policy) {
+            self.recover_stale_state();
+        }
+        self.recovered.store(true, Ordering::SeqCst);
     }
 
-    fn kill_child(&self) {
-        let mut child_lock = self.child.lock().unwrap();
-        if let Some(ref mut child) = *child_lock {
-            let pid = child.id();
-            log::info!("Stopping tunnel (PID: {})", pid);
+    /// Undo routing state left by a previous run.
+    fn recover_stale_state(&self) {
+        if self.routing_enabled {
+            routing::recover_stale_state(&self.names, &self.journal);
+        }
+        if let Some(ks) = &self.killswitch {
+            ks.release();
+        }
+    }
 
-            // Try graceful termination first on Unix.
-            #[cfg(unix)]
-            {
-                let _ = nix::sys::signal::kill(
-                    nix::unistd::Pid::from_raw(pid as i32),
-                    nix::sys::signal::Signal::SIGTERM,
-                );
+    /// Find a client left running by a previous wrapper, for example after a
+    /// crash, and adopt or terminate it. Returns whether the client was
+    /// adopted, in which case its routing is still in place and must not be
+    /// recovered.
+    fn reclaim_orphan(&self, policy: OrphanPolicy) -> bool {
+        let Some(pid) = process::find_orphan(&self.client_pidfile, CLIENT_BIN, &self.client_toml)
+        else {
+            process::remove_pidfile(&self.client_pidfile);
+            return false;
+        };
+        if policy == OrphanPolicy::Kill {
+            let msg = format!(
+                "[tunnel] stopping client PID {} left by a previous run",
+                pid
+            );
+            log::warn!("{}", msg);
+            logs::global_buffer().push(msg);
+            ClientProcess::adopted(pid, CLIENT_BIN, &self.client_toml).terminate();
+            process::remove_pidfile(&self.client_pidfile);
+            return false;
+        }
+
+        let msg = format!("[tunnel] adopted client PID {} left by a previous run", pid);
+        log::info!("{}", msg);
+        logs::global_buffer().push(msg.clone());
+        self.events.record("adopted", msg);
+        process::write_pidfile(&self.client_pidfile, pid);
+        let addresses = self.settings.lock().unwrap().addresses.clone();
+        let last_wan = self.last_wan_interface.lock().unwrap().clone();
+        let (ips, _) = resolve_over_wan(&addresses, &self.resolve_servers, &last_wan);
+        *self.server_ips.lock().unwrap() = ips;
+
+        *self.child.lock().unwrap() =
+            Some(ClientProcess::adopted(pid, CLIENT_BIN, &self.client_toml));
+        *self.connect_time.lock().unwrap() = Some(Instant::now());
+        self.traffic.start_session();
+        self.begin_session();
+        {
+            let mut st = self.status.lock().unwrap();
+            st.connected = true;
+            st.pid = Some(pid);
+            st.last_error.clear();
+        }
+        self.routing_active.store(
+            self.routing_enabled && !self.journal.lock().unwrap().is_empty(),
+            Ordering::SeqCst,
+        );
+        self.should_stop.store(false, Ordering::SeqCst);
+        self.running.store(true, Ordering::SeqCst);
+        true
+    }
+
+    /// NDM's view of this instance's interface and routes.
+    pub fn ndm_diagnostics(&self) -> routing::NdmDiagnostics {
+        routing::ndm_diagnostics(&self.names)
+    }
+
+    /// Results of the most recent watchdog probe round.
+    pub fn last_probe_round(&self) -> Option<ProbeRound> {
+        self.last_probe_round.lock().unwrap().clone()
+    }
+
+    /// Results of the most recent probe round sent via WAN.
+    pub fn last_wan_round(&self) -> Option<ProbeRound> {
+        self.last_wan_round.lock().unwrap().clone()
+    }
+
+    pub fn waiting_for_wan(&self) -> bool {
+        self.waiting_for_wan.load(Ordering::SeqCst)
+    }
+
+    /// Run the watchdog probes out of the current WAN interface. No WAN
+    /// default route counts as WAN down.
+    fn probe_wan(&self) -> bool {
+        let Some(wan) = routing::current_wan_interface() else {
+            *self.last_wan_round.lock().unwrap() = None;
+            return false;
+        };
+        let round = probes::run_all(
+            &self.watchdog_probes,
+            self.watchdog_quorum,
+            &wan,
+            self.watchdog_check_timeout,
+        );
+        let healthy = round.healthy;
+        *self.last_wan_round.lock().unwrap() = Some(round);
+        healthy
+    }
+
+    /// While waiting for WAN, re-probe it every watchdog interval and
+    /// reconnect once when it answers.
+    fn poll_wan_recovery(&self, reconnect_delay: u64) {
+        if self.last_watchdog_check.lock().unwrap().elapsed() < self.watchdog_interval {
+            return;
+        }
+        *self.last_watchdog_check.lock().unwrap() = Instant::now();
+        if !self.probe_wan() {
+            log::debug!("[watchdog] WAN still down");
+            return;
+        }
+        self.waiting_for_wan.store(false, Ordering::SeqCst);
+        let msg = "[watchdog] WAN is back, reconnecting the tunnel".to_string();
+        log::info!("{}", msg);
+        logs::global_buffer().push(msg.clone());
+        self.events.record("wan_up", msg);
+        self.full_restart("WAN recovered", reconnect_delay);
+    }
+
+    /// Resolve the DNS probe through the router and report the upstream paths.
+    pub fn dns_leak_check(&self, settings: &DnsSettings) -> dns::LeakReport {
+        dns::leak_check(&self.names, settings)
+    }
+
+    /// Connect even though the quota is exhausted; the override lasts until
+    /// the quota period ends.
+    pub fn start_overriding_quota(&self) -> Result<(), String> {
+        if let Some(quota) = &self.quota {
+            if quota.set_override() {
+                let msg = "[quota] override: connecting past the limit until the period ends";
+                log::info!("{}", msg);
+                logs::global_buffer().push(msg.to_string());
             }
+        }
+        self.start()
+    }
+
+    /// Quota usage of the current period; `None` when no quota is set.
+    pub fn quota_status(&self) -> Option<QuotaStatus> {
+        self.quota.as_ref().map(|q| q.status())
+    }
 
-            // Wait up to 5 seconds
-            for _ in 0..50 {
-                match child.try_wait() {
-                    Ok(Some(_)) => break,
-                    _ => std::thread::sleep(Duration::from_millis(100)),
+    /// Clear the usage of the current quota period. Returns false when no
+    /// quota is set.
+    pub fn reset_quota(&self) -> bool {
+        let Some(quota) = &self.quota else {
+            return false;
+        };
+        quota.reset();
+        self.events
+            .record("quota_reset", "quota usage reset".to_string());
+        true
+    }
+
+    /// Feed the traffic total into the quota and act on what it reports.
+    fn check_quota(&self) {
+        let Some(quota) = &self.quota else {
+            return;
+        };
+        let total = self.traffic.snapshot().total;
+        for event in quota.update(total.rx_bytes + total.tx_bytes) {
+            let (kind, msg) = match event {
+                QuotaEvent::PeriodReset => {
+                    ("quota_reset", "[quota] new period started".to_string())
                 }
+                QuotaEvent::Warning(pct) => (
+                    "quota_warning",
+                    format!("[quota] {}% of the data quota used", pct),
+                ),
+                QuotaEvent::Exhausted => (
+                    "quota_exhausted",
+                    format!("[quota] data quota exhausted, action: {}", quota.action()),
+                ),
+            };
+            log::warn!("{}", msg);
+            logs::global_buffer().push(msg.clone());
+            self.events.record(kind, msg.clone());
+            if event != QuotaEvent::Exhausted || !quota.exhausted() {
+                continue;
+            }
+            match quota.action() {
+                QuotaAction::Disconnect => self.stop(StopReason::Quota),
+                QuotaAction::Block => {
+                    self.status.lock().unwrap().last_error = msg;
+                    self.kill_child(StopReason::Quota);
+                    self.teardown_if_active(TeardownLevel::Soft);
+                    self.sync_killswitch();
+                }
+                QuotaAction::Log => {}
             }
+        }
+    }
 
-            // Force kill if still alive
-            let _ = child.kill();
-            let _ = child.wait();
+    /// The client is held down by an exhausted quota with the `block` action.
+    fn quota_blocks(&self) -> bool {
+        self.quota
+            .as_ref()
+            .is_some_and(|q| q.exhausted_with(QuotaAction::Block))
+    }
+
+    /// Disconnect now and reconnect after `secs` seconds.
+    pub fn pause(&self, secs: u64) -> Result<(), String> {
+        if !self.running.load(Ordering::SeqCst) {
+            return Err("Tunnel is not running".into());
+        }
+        self.stop(StopReason::Pause);
+        *self.paused_until.lock().unwrap() = Some(unix_now() + secs);
+        let msg = format!("[tunnel] paused for {}s", secs);
+        log::info!("{}", msg);
+        logs::global_buffer().push(msg.clone());
+        self.events.record("paused", msg);
+        Ok(())
+    }
+
+    /// Carry a pause saved by a previous run over: stay down (stopping an
+    /// adopted client) and reconnect after `secs` seconds.
+    pub fn restore_pause(&self, secs: u64) {
+        if self.running.load(Ordering::SeqCst) {
+            self.stop(StopReason::Pause);
+        }
+        *self.paused_until.lock().unwrap() = Some(unix_now() + secs);
+        let msg = format!("[tunnel] still paused, reconnecting in {}s", secs);
+        log::info!("{}", msg);
+        logs::global_buffer().push(msg);
+    }
+
+    /// End a pause early and reconnect.
+    pub fn resume(&self) -> Result<(), String> {
+        if self.paused_until.lock().unwrap().is_none() {
+            return Err("Tunnel is not paused".into());
+        }
+        self.events
+            .record("resumed", "[tunnel] resumed by request".to_string());
+        self.start()
+    }
+
+    /// Seconds until a paused tunnel reconnects; `None` when not paused.
+    pub fn pause_remaining(&self) -> Option<u64> {
+        self.paused_until
+            .lock()
+            .unwrap()
+            .map(|until| until.saturating_sub(unix_now()))
+    }
+
+    /// Reconnect once the pause is over.
+    fn check_pause_expiry(&self) {
+        let expired = self
+            .paused_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| unix_now() >= until);
+        if !expired {
+            return;
+        }
+        let msg = "[tunnel] pause is over, reconnecting".to_string();
+        log::info!("{}", msg);
+        logs::global_buffer().push(msg.clone());
+        self.events.record("resumed", msg);
+        if let Err(e) = self.start() {
+            // The pause is over either way; do not retry every tick.
+            *self.paused_until.lock().unwrap() = None;
+            let msg = format!("[tunnel] reconnect after pause failed: {}", e);
+            log::warn!("{}", msg);
+            logs::global_buffer().push(msg.clone());
+            self.status.lock().unwrap().last_error = msg;
+        }
+    }
+
+    /// Stop the tunnel and remove all routing state (explicit disconnect,
+    /// process shutdown).
+    pub fn stop(&self, reason: StopReason) {
+        *self.paused_until.lock().unwrap() = None;
+        self.stop_with(TeardownLevel::Full, reason);
+        if let Some(quota) = &self.quota {
+            quota.persist();
+        }
+        if let Some(ks) = &self.killswitch {
+            ks.release();
+        }
+    }
+
+    fn stop_with(&self, level: TeardownLevel, reason: StopReason) {
+        self.should_stop.store(true, Ordering::SeqCst);
+        self.running.store(false, Ordering::SeqCst);
+        self.waiting_for_wan.store(false, Ordering::SeqCst);
+        self.kill_child(reason);
+        self.teardown_if_active(level);
+    }
+
+    fn kill_child(&self, reason: StopReason) {
+        let mut child_lock = self.child.lock().unwrap();
+        let mut exit = None;
+        if let Some(ref mut child) = *child_lock {
+            log::info!("Stopping tunnel (PID: {})", child.id());
+            exit = child.terminate();
+            process::remove_pidfile(&self.client_pidfile);
         }
         *child_lock = None;
+        drop(child_lock);
+        self.end_session(reason, exit.as_ref());
 
         let mut st = self.status.lock().unwrap();
         st.connected = false;
@@ -231,13 +834,36 @@ impl TunnelManager {
     }
 
     pub fn restart(&self) -> Result<(), String> {
-        self.stop();
+        self.restart_with(StopReason::User)
+    }
+
+    fn restart_with(&self, reason: StopReason) -> Result<(), String> {
+        // Keep the NDM interface object; the new client reuses it.
+        self.stop_with(TeardownLevel::Soft, reason);
         std::thread::sleep(Duration::from_secs(1));
         self.start()
     }
 
+    /// The client only reads its routes at startup, so restart it when a
+    /// route list refresh changed them.
+    fn apply_route_list_changes(&self) {
+        if self.route_lists.generation() == self.route_lists_applied.load(Ordering::SeqCst) {
+            return;
+        }
+        let msg = "[routes] route lists changed, restarting the client to apply them".to_string();
+        log::info!("{}", msg);
+        logs::global_buffer().push(msg);
+        if let Err(e) = self.restart_with(StopReason::RouteLists) {
+            let msg = format!("[routes] restart after route list change failed: {}", e);
+            log::warn!("{}", msg);
+            logs::global_buffer().push(msg.clone());
+            self.status.lock().unwrap().last_error = msg;
+        }
+    }
+
     fn respawn_with_delay(&self, reconnect_delay: u64) {
-        self.teardown_if_active();
+        self.teardown_if_active(TeardownLevel::Soft);
+        self.sync_killswitch();
 
         log::info!("Reconnecting in {} seconds...", reconnect_delay);
         logs::global_buffer().push(format!("[tunnel] reconnecting in {}s...", reconnect_delay));
@@ -260,10 +886,96 @@ impl TunnelManager {
         logs::global_buffer().push(msg.clone());
         self.status.lock().unwrap().last_error = msg;
 
-        self.kill_child();
+        self.kill_child(StopReason::Watchdog);
         self.respawn_with_delay(reconnect_delay);
     }
 
+    /// Take one ladder step and record it in the event history.
+    fn escalate(&self, action: RecoveryAction, rung: usize, fails: u32, reconnect_delay: u64) {
+        let msg = format!(
+            "[watchdog] rung {} ({}) after {} failed checks",
+            rung + 1,
+            action,
+            fails
+        );
+        log::warn!("{}", msg);
+        logs::global_buffer().push(msg.clone());
+        self.events.record("recovery", msg);
+
+        let result = match action {
+            RecoveryAction::ReassertRoutes => {
+                let wan = match self.last_wan_interface.lock().unwrap().clone() {
+                    wan if wan.is_empty() => routing::current_wan_interface().unwrap_or_default(),
+                    wan => wan,
+                };
+                let ips = self.server_ips.lock().unwrap().clone();
+                routing::reassert_routes(
+                    &self.names,
+                    &self.route_scope(),
+                    &ips,
+                    &wan,
+                    &self.journal,
+                )
+            }
+            RecoveryAction::ReapplyNdm => {
+                routing::apply_ndm_interface_settings(&self.names, &self.journal)
+            }
+            RecoveryAction::RestartClient => {
+                self.full_restart("connectivity lost", reconnect_delay);
+                Ok(())
+            }
+            RecoveryAction::ResetRouting => {
+                self.kill_child(StopReason::Watchdog);
+                self.teardown_if_active(TeardownLevel::Full);
+                self.respawn_with_delay(reconnect_delay);
+                Ok(())
+            }
+            RecoveryAction::SwitchEndpoint => {
+                {
+                    let mut settings = self.settings.lock().unwrap();
+                    if settings.addresses.len() > 1 {
+                        settings.addresses.rotate_left(1);
+                        self.events.record(
+                            "recovery",
+                            format!("endpoint switched to {}", settings.addresses[0]),
+                        );
+                    }
+                }
+                self.full_restart("switching endpoint", reconnect_delay);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            let msg = format!("[watchdog] {} failed: {}", action, e);
+            log::warn!("{}", msg);
+            logs::global_buffer().push(msg.clone());
+            self.events.record("recovery_failed", msg);
+        }
+    }
+
+    /// Quality series of the last `minutes` minutes; `None` when sampling is off.
+    pub fn quality_series(
+        &self,
+        minutes: u64,
+    ) -> Option<std::collections::BTreeMap<String, Vec<crate::quality::QualityPoint>>> {
+        self.quality.as_ref().map(|q| q.series(minutes))
+    }
+
+    /// Byte/packet counters and current rates of the tunnel interface.
+    pub fn traffic(&self) -> TrafficSnapshot {
+        self.traffic.snapshot()
+    }
+
+    /// Throughput history of the tunnel interface, oldest first.
+    pub fn traffic_history(&self, resolution: Resolution) -> Vec<TrafficBucket> {
+        self.traffic.history(resolution)
+    }
+
+    /// Recent events of this tunnel, newest last.
+    pub fn events(&self, count: usize) -> Vec<Event> {
+        self.events.recent(count)
+    }
+
     fn reroute(&self, new_wan: &str) {
         let msg = format!(
             "[watchdog] WAN changed → {}, updating server routes",
@@ -272,12 +984,64 @@ impl TunnelManager {
         log::info!("{}", msg);
         logs::global_buffer().push(msg);
 
-        let addresses = self.settings.lock().unwrap().addresses.clone();
-        routing::reroute_server_via_wan(&addresses, new_wan);
+        let ips = self.server_ips.lock().unwrap().clone();
+        routing::reroute_server_via_wan(&ips, new_wan, &self.journal);
         *self.last_wan_interface.lock().unwrap() = new_wan.to_string();
         self.watchdog_failures.store(0, Ordering::SeqCst);
     }
 
+    /// Re-resolve hostname endpoints and move server host routes to the new
+    /// answers. A failed lookup keeps the current routes.
+    fn refresh_server_ips(&self) {
+        if self.last_resolve.lock().unwrap().elapsed() < self.resolve_interval {
+            return;
+        }
+        *self.last_resolve.lock().unwrap() = Instant::now();
+
+        let addresses = self.settings.lock().unwrap().addresses.clone();
+        if !routing::has_hostnames(&addresses) {
+            return;
+        }
+        // The default route (and possibly the DNS proxy) points into the
+        // tunnel now; ask the servers directly over the WAN instead.
+        let wan = self.last_wan_interface.lock().unwrap().clone();
+        if wan.is_empty() {
+            return;
+        }
+        let (ips, errors) = routing::resolve_server_ips_via(
+            &addresses,
+            &self.resolve_servers,
+            &wan,
+            RESOLVE_TIMEOUT,
+        );
+        if !errors.is_empty() {
+            log::warn!(
+                "[watchdog] server re-resolution failed, keeping routes: {}",
+                errors.join("; ")
+            );
+            return;
+        }
+
+        let mut current = self.server_ips.lock().unwrap();
+        let mut sorted_new = ips.clone();
+        let mut sorted_old = current.clone();
+        sorted_new.sort();
+        sorted_old.sort();
+        if sorted_new == sorted_old {
+            return;
+        }
+
+        let msg = format!(
+            "[watchdog] server IPs changed: {:?} -> {:?}",
+            sorted_old, sorted_new
+        );
+        log::info!("{}", msg);
+        logs::global_buffer().push(msg);
+
+        routing::update_server_routes(&ips, &wan, &self.journal);
+        *current = ips;
+    }
+
     fn run_watchdog_check(&self, reconnect_delay: u64) {
         if !self.watchdog_enabled || !self.routing_active.load(Ordering::SeqCst) {
             return;
@@ -289,7 +1053,12 @@ impl TunnelManager {
         }
         *self.last_watchdog_check.lock().unwrap() = Instant::now();
 
-        if !routing::is_tun_alive() {
+        if self.waiting_for_wan.load(Ordering::SeqCst) {
+            self.poll_wan_recovery(reconnect_delay);
+            return;
+        }
+
+        if !routing::is_tun_alive(&self.names) {
             self.full_restart("OpkgTun0 interface disappeared", reconnect_delay);
             return;
         }
@@ -303,24 +1072,91 @@ impl TunnelManager {
             }
         }
 
-        if !routing::check_connectivity(&self.watchdog_check_url, self.watchdog_check_timeout) {
+        self.refresh_server_ips();
+
+        let round = probes::run_all(
+            &self.watchdog_probes,
+            self.watchdog_quorum,
+            &self.names.opkg_tun,
+            self.watchdog_check_timeout,
+        );
+        let mut healthy = round.healthy;
+        let (passed, quorum) = (round.passed, round.quorum);
+        *self.last_probe_round.lock().unwrap() = Some(round);
+
+        if let Some(quality) = &self.quality {
+            if let Some(loss) = quality.sustained_loss() {
+                log::warn!("[watchdog] sustained packet loss {:.1}% via tunnel", loss);
+                healthy = false;
+            }
+        }
+
+        if !healthy && self.watchdog_wan_check && !self.probe_wan() {
+            self.waiting_for_wan.store(true, Ordering::SeqCst);
+            self.watchdog_failures.store(0, Ordering::SeqCst);
+            self.ladder_rung.store(0, Ordering::SeqCst);
+            let msg =
+                "[watchdog] WAN is down as well, holding restarts until it recovers".to_string();
+            log::warn!("{}", msg);
+            logs::global_buffer().push(msg.clone());
+            self.events.record("wan_down", msg.clone());
+            self.status.lock().unwrap().last_error = msg;
+        } else if !healthy {
             let fails = self.watchdog_failures.fetch_add(1, Ordering::SeqCst) + 1;
+            let index = self
+                .ladder_rung
+                .load(Ordering::SeqCst)
+                .min(self.watchdog_ladder.len() - 1);
+            let rung = &self.watchdog_ladder[index];
             log::warn!(
-                "[watchdog] connectivity check failed ({}/{})",
+                "[watchdog] connectivity check failed: {}/{} probes passed, quorum {} ({}/{} before {})",
+                passed,
+                self.watchdog_probes.len(),
+                quorum,
                 fails,
-                self.watchdog_max_failures
+                rung.failures,
+                rung.action
             );
-            if fails >= self.watchdog_max_failures {
-                self.full_restart(
-                    &format!("connectivity lost ({} failures)", fails),
-                    reconnect_delay,
+            if fails >= rung.failures {
+                self.watchdog_failures.store(0, Ordering::SeqCst);
+                // The last rung repeats until a check passes.
+                self.ladder_rung.store(
+                    (index + 1).min(self.watchdog_ladder.len() - 1),
+                    Ordering::SeqCst,
                 );
+                self.escalate(rung.action, index, fails, reconnect_delay);
             }
         } else {
             let prev = self.watchdog_failures.swap(0, Ordering::SeqCst);
-            if prev > 0 {
+            let rung = self.ladder_rung.swap(0, Ordering::SeqCst);
+            if prev > 0 || rung > 0 {
                 log::info!("[watchdog] connectivity restored");
+                if rung > 0 {
+                    self.events
+                        .record("recovered", "connectivity restored".to_string());
+                }
+            }
+        }
+    }
+
+    /// Traffic sampling loop, apart from the monitor -- call from a dedicated thread.
+    pub fn traffic_loop(&self) {
+        loop {
+            self.traffic.sample(&self.names.opkg_tun);
+            std::thread::sleep(crate::traffic::SAMPLE_INTERVAL);
+        }
+    }
+
+    /// Quality sampling loop, apart from the monitor -- call from a dedicated thread.
+    pub fn quality_loop(&self) {
+        let Some(quality) = &self.quality else {
+            return;
+        };
+        loop {
+            if self.running.load(Ordering::SeqCst) && self.routing_active.load(Ordering::SeqCst) {
+                quality.sample(&self.names.opkg_tun);
             }
+            std::thread::sleep(quality.interval());
         }
     }
 
@@ -329,8 +1165,12 @@ impl TunnelManager {
     pub fn monitor_loop(self: &Arc<Self>) {
         let reconnect_delay = self.settings.lock().unwrap().reconnect_delay;
 
-        while !self.should_stop.load(Ordering::SeqCst) {
-            if !self.running.load(Ordering::SeqCst) {
+        // Runs for the life of the process so a later `start` is monitored
+        // too; `should_stop` only pauses it.
+        loop {
+            self.check_quota();
+            self.check_pause_expiry();
+            if !self.running.load(Ordering::SeqCst) || self.should_stop.load(Ordering::SeqCst) {
                 std::thread::sleep(Duration::from_millis(500));
                 continue;
             }
@@ -339,10 +1179,10 @@ impl TunnelManager {
             let exited = {
                 let mut child_lock = self.child.lock().unwrap();
                 if let Some(ref mut child) = *child_lock {
-                    match child.try_wait() {
-                        Ok(Some(exit)) => {
-                            let msg =
-                                format!("[tunnel] process exited: {}", exit.code().unwrap_or(-1));
+                    match child.poll() {
+                        Ok(Poll::Exited(exit)) => {
+                            let code = exit.and_then(|e| e.code()).unwrap_or(-1);
+                            let msg = format!("[tunnel] process exited: {}", code);
                             log::warn!("{}", msg);
                             logs::global_buffer().push(msg.clone());
                             {
@@ -352,9 +1192,12 @@ impl TunnelManager {
                                 st.pid = None;
                             }
                             *child_lock = None;
+                            drop(child_lock);
+                            process::remove_pidfile(&self.client_pidfile);
+                            self.end_session(StopReason::Crash, exit.as_ref());
                             true
                         }
-                        Ok(None) => false,
+                        Ok(Poll::Running) => false,
                         Err(e) => {
                             log::error!("Error checking child: {}", e);
                             false
@@ -365,14 +1208,22 @@ impl TunnelManager {
                 }
             };
 
-            if exited
+            if exited && self.quota_blocks() {
+                // Held down until the period resets or the quota is reset.
+            } else if exited
                 && self.running.load(Ordering::SeqCst)
                 && !self.should_stop.load(Ordering::SeqCst)
             {
-                self.respawn_with_delay(reconnect_delay);
+                if self.waiting_for_wan.load(Ordering::SeqCst) {
+                    self.poll_wan_recovery(reconnect_delay);
+                } else {
+                    self.respawn_with_delay(reconnect_delay);
+                }
             } else if !exited {
+                self.apply_route_list_changes();
                 self.run_watchdog_check(reconnect_delay);
             }
+            self.sync_killswitch();
 
             std::thread::sleep(Duration::from_millis(500));
         }
@@ -0,0 +1,37 @@
+use std::path::Path;
+
+/// Current Unix time, seconds.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Replace `path` with `content` through a temporary file and a rename, so
+/// a crash or power loss never leaves a half-written file. Creates the
+/// parent directory.
+pub fn atomic_write(path: &Path, content: impl AsRef<[u8]>) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atomic_write_creates_parent_and_replaces() {
+        let dir = std::env::temp_dir().join(format!("tt-util-{}", std::process::id()));
+        let path = dir.join("nested").join("state.json");
+        atomic_write(&path, "first").unwrap();
+        atomic_write(&path, b"second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert!(!path.with_extension("tmp").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
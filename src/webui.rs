@@ -1,6 +1,12 @@
 use crate::auth;
 use crate::config::{TunnelSettings, WrapperConfig};
 use crate::logs;
+use crate::registry::{TunnelRegistry, MAIN_TUNNEL_ID};
+use crate::routelists::RouteLists;
+use crate::scheduler::Scheduler;
+use crate::sessions::StopReason;
+use crate::state::DesiredState;
+use crate::traffic::Resolution;
 use crate::tunnel::TunnelManager;
 use std::collections::HashMap;
 use std::io::Read;
@@ -10,6 +16,8 @@ use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
 
 const SESSION_TTL_SECS: u64 = 3600;
 const MAX_BODY_BYTES: usize = 64 * 1024;
+/// Longest pause accepted by `POST /api/control`.
+const MAX_PAUSE_SECS: u64 = 7 * 24 * 3600;
 const INDEX_HTML: &str = include_str!("../package/www/index.html");
 const WEBUI_VERSION: &str = env!("TRUSTTUNNEL_VERSION");
 
@@ -50,7 +58,9 @@ impl Sessions {
 }
 
 pub struct WebUI {
-    tunnel: Arc<TunnelManager>,
+    tunnels: Arc<TunnelRegistry>,
+    route_lists: Arc<RouteLists>,
+    scheduler: Option<Arc<Scheduler>>,
     config: Arc<Mutex<WrapperConfig>>,
     config_path: String,
     sessions: Mutex<Sessions>,
@@ -60,14 +70,18 @@ pub struct WebUI {
 
 impl WebUI {
     pub fn new(
-        tunnel: Arc<TunnelManager>,
+        tunnels: Arc<TunnelRegistry>,
+        route_lists: Arc<RouteLists>,
+        scheduler: Option<Arc<Scheduler>>,
         config: Arc<Mutex<WrapperConfig>>,
         config_path: String,
         ndm_host: String,
         ndm_port: u16,
     ) -> Arc<Self> {
         Arc::new(Self {
-            tunnel,
+            tunnels,
+            route_lists,
+            scheduler,
             config,
             config_path,
             sessions: Mutex::new(Sessions::new()),
@@ -76,19 +90,19 @@ impl WebUI {
         })
     }
 
-    pub fn run(self: &Arc<Self>, bind: &str, port: u16) {
+    /// Open the listening socket; requests wait until `run`. This already
+    /// starts the server's threads, so termination signals must be blocked
+    /// first.
+    pub fn bind(bind: &str, port: u16) -> Result<Server, String> {
         let addr = format!("{}:{}", bind, port);
-        let server = match Server::http(&addr) {
-            Ok(s) => s,
-            Err(e) => {
-                log::error!("Failed to start WebUI on {}: {}", addr, e);
-                return;
-            }
-        };
-
+        let server =
+            Server::http(&addr).map_err(|e| format!("Failed to start WebUI on {}: {}", addr, e))?;
         log::info!("WebUI listening on http://{}", addr);
         logs::global_buffer().push(format!("[webui] listening on http://{}", addr));
+        Ok(server)
+    }
 
+    pub fn run(self: &Arc<Self>, server: Server) {
         for request in server.incoming_requests() {
             let resp = self.handle_request(request);
             // Response is sent inside handle_request via request.respond()
@@ -103,17 +117,53 @@ impl WebUI {
         let result = match (method, path.as_str()) {
             (Method::Get, "/") | (Method::Get, "/index.html") => self.serve_index(&request),
             (Method::Post, "/api/login") => self.api_login(&mut request),
-            (Method::Get, "/api/status") => self.api_authed(&request, |s| s.api_status()),
-            (Method::Get, "/api/config") => self.api_authed(&request, |s| s.api_get_config()),
+            (Method::Get, "/api/status") => {
+                self.api_authed(&request, |s| s.api_status(MAIN_TUNNEL_ID))
+            }
+            (Method::Get, "/api/config") => {
+                self.api_authed(&request, |s| s.api_get_config(MAIN_TUNNEL_ID))
+            }
             (Method::Post, "/api/config") => match read_body(&mut request) {
-                Ok(body) => self.api_authed(&request, |s| s.api_set_config(&body)),
+                Ok(body) => self.api_authed(&request, |s| s.api_set_config(MAIN_TUNNEL_ID, &body)),
                 Err(e) => json_response(413, &serde_json::json!({"error": e}).to_string()),
             },
             (Method::Post, "/api/control") => match read_body(&mut request) {
-                Ok(body) => self.api_authed(&request, |s| s.api_control(&body)),
+                Ok(body) => self.api_authed(&request, |s| s.api_control(MAIN_TUNNEL_ID, &body)),
                 Err(e) => json_response(413, &serde_json::json!({"error": e}).to_string()),
             },
+            (Method::Get, "/api/tunnels") => self.api_authed(&request, |s| s.api_tunnels()),
+            (method, p) if p.starts_with("/api/tunnels/") => {
+                self.handle_tunnel_request(method, p, &mut request)
+            }
             (Method::Get, "/api/logs") => self.api_authed(&request, |s| s.api_logs(&request)),
+            (Method::Get, "/api/metrics/quality") => {
+                self.api_authed(&request, |s| s.api_quality(MAIN_TUNNEL_ID, &request))
+            }
+            (Method::Get, "/api/metrics/traffic") => {
+                self.api_authed(&request, |s| s.api_traffic(MAIN_TUNNEL_ID, &request))
+            }
+            (Method::Get, "/api/quota") => {
+                self.api_authed(&request, |s| s.api_quota(MAIN_TUNNEL_ID))
+            }
+            (Method::Post, "/api/quota/reset") => {
+                self.api_authed(&request, |s| s.api_quota_reset(MAIN_TUNNEL_ID))
+            }
+            (Method::Get, "/api/sessions") => {
+                self.api_authed(&request, |s| s.api_sessions(MAIN_TUNNEL_ID, &request))
+            }
+            (Method::Get, "/api/events") => {
+                self.api_authed(&request, |s| s.api_events(MAIN_TUNNEL_ID, &request))
+            }
+            (Method::Get, "/api/route-lists") => self.api_authed(&request, |s| s.api_route_lists()),
+            (Method::Post, "/api/route-lists/refresh") => {
+                self.api_authed(&request, |s| s.api_route_lists_refresh())
+            }
+            (Method::Get, "/api/diagnostics/ndm") => {
+                self.api_authed(&request, |s| s.api_diagnostics_ndm(MAIN_TUNNEL_ID))
+            }
+            (Method::Get, "/api/diagnostics/dns") => {
+                self.api_authed(&request, |s| s.api_diagnostics_dns())
+            }
             _ => json_response(404, r#"{"error":"not found"}"#),
         };
 
@@ -177,24 +227,81 @@ impl WebUI {
         handler(self)
     }
 
-    fn api_status(&self) -> Response<std::io::Cursor<Vec<u8>>> {
-        let st = self.tunnel.get_status();
-        let body = serde_json::json!({
+    /// Look up a tunnel; unknown IDs map to a 404 response.
+    fn tunnel_or_404(
+        &self,
+        id: &str,
+    ) -> Result<&Arc<TunnelManager>, Response<std::io::Cursor<Vec<u8>>>> {
+        self.tunnels.get(id).ok_or_else(|| {
+            json_response(
+                404,
+                &serde_json::json!({"error": format!("unknown tunnel '{}'", id)}).to_string(),
+            )
+        })
+    }
+
+    fn status_json(&self, id: &str, tunnel: &TunnelManager) -> serde_json::Value {
+        let st = tunnel.get_status();
+        serde_json::json!({
+            "id": id,
             "connected": st.connected,
             "uptime_seconds": st.uptime_seconds,
             "last_error": st.last_error,
             "pid": st.pid,
-        });
-        json_response(200, &body.to_string())
+            "server_ips": st.server_ips.iter().map(|ip| ip.to_string()).collect::<Vec<_>>(),
+            "ndm_interface": tunnel.interface_names().ndm,
+            "default_route": tunnel.owns_default_route(),
+            "killswitch_engaged": tunnel.killswitch_engaged(),
+            "watchdog": tunnel.last_probe_round(),
+            "waiting_for_wan": tunnel.waiting_for_wan(),
+            "wan_watchdog": tunnel.last_wan_round(),
+            "traffic": tunnel.traffic(),
+            "quota": tunnel.quota_status(),
+            "schedule": self.scheduler.as_ref().and_then(|s| s.status(id)),
+            "pause_remaining_seconds": tunnel.pause_remaining(),
+        })
     }
 
-    fn api_get_config(&self) -> Response<std::io::Cursor<Vec<u8>>> {
+    fn api_status(&self, id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+        match self.tunnel_or_404(id) {
+            Ok(tunnel) => json_response(200, &self.status_json(id, tunnel).to_string()),
+            Err(resp) => resp,
+        }
+    }
+
+    fn api_tunnels(&self) -> Response<std::io::Cursor<Vec<u8>>> {
+        let tunnels: Vec<serde_json::Value> = self
+            .tunnels
+            .iter()
+            .map(|(id, tunnel)| self.status_json(id, tunnel))
+            .collect();
+        json_response(200, &serde_json::json!({ "tunnels": tunnels }).to_string())
+    }
+
+    fn api_get_config(&self, id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
         let cfg = self.config.lock().unwrap();
-        let body = serde_json::to_string(&cfg.tunnel).unwrap_or_default();
-        json_response(200, &body)
+        let settings = if id == MAIN_TUNNEL_ID {
+            Some(&cfg.tunnel)
+        } else {
+            cfg.tunnels.iter().find(|t| t.id == id).map(|t| &t.tunnel)
+        };
+        match settings {
+            Some(settings) => {
+                let body = serde_json::to_string(settings).unwrap_or_default();
+                json_response(200, &body)
+            }
+            None => json_response(
+                404,
+                &serde_json::json!({"error": format!("unknown tunnel '{}'", id)}).to_string(),
+            ),
+        }
     }
 
-    fn api_set_config(&self, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    fn api_set_config(&self, id: &str, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+        let tunnel = match self.tunnel_or_404(id) {
+            Ok(t) => t,
+            Err(resp) => return resp,
+        };
         let new_tunnel: TunnelSettings = match serde_json::from_str(body) {
             Ok(v) => v,
             Err(e) => {
@@ -209,7 +316,11 @@ impl WebUI {
         {
             let mut cfg = self.config.lock().unwrap();
             let mut next_cfg = cfg.clone();
-            next_cfg.tunnel = new_tunnel.clone();
+            if id == MAIN_TUNNEL_ID {
+                next_cfg.tunnel = new_tunnel.clone();
+            } else if let Some(extra) = next_cfg.tunnels.iter_mut().find(|t| t.id == id) {
+                extra.tunnel = new_tunnel.clone();
+            }
             if let Err(e) = next_cfg.save(&self.config_path) {
                 log::error!("Failed to save config: {}", e);
                 return json_response(
@@ -222,30 +333,92 @@ impl WebUI {
         }
 
         // Update tunnel settings (will take effect on next restart)
-        self.tunnel.update_settings(new_tunnel);
+        tunnel.update_settings(new_tunnel);
 
         json_response(200, r#"{"status":"updated"}"#)
     }
 
-    fn api_control(&self, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    /// A manual connect or disconnect of `id` holds against the schedule;
+    /// only record it once the action succeeded.
+    fn note_manual(&self, id: &str) {
+        if let Some(scheduler) = &self.scheduler {
+            scheduler.note_manual(id);
+        }
+    }
+
+    fn api_control(&self, id: &str, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+        let tunnel = match self.tunnel_or_404(id) {
+            Ok(t) => t,
+            Err(resp) => return resp,
+        };
         let parsed: serde_json::Value = match serde_json::from_str(body) {
             Ok(v) => v,
             Err(_) => return json_response(400, r#"{"error":"invalid json"}"#),
         };
 
         let action = parsed.get("action").and_then(|v| v.as_str()).unwrap_or("");
-
         match action {
-            "connect" => match self.tunnel.start() {
-                Ok(()) => json_response(200, r#"{"status":"connecting"}"#),
-                Err(e) => json_response(400, &serde_json::json!({"error": e}).to_string()),
-            },
+            "connect" => {
+                let override_quota = parsed
+                    .get("override_quota")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                let result = if override_quota {
+                    tunnel.start_overriding_quota()
+                } else {
+                    tunnel.start()
+                };
+                match result {
+                    Ok(()) => {
+                        self.note_manual(id);
+                        self.tunnels.set_desired(id, DesiredState::Connected);
+                        json_response(200, r#"{"status":"connecting"}"#)
+                    }
+                    Err(e) => json_response(400, &serde_json::json!({"error": e}).to_string()),
+                }
+            }
             "disconnect" => {
-                self.tunnel.stop();
+                tunnel.stop(StopReason::User);
+                self.note_manual(id);
+                self.tunnels.set_desired(id, DesiredState::Disconnected);
                 json_response(200, r#"{"status":"disconnected"}"#)
             }
-            "restart" => match self.tunnel.restart() {
-                Ok(()) => json_response(200, r#"{"status":"restarting"}"#),
+            "pause" => {
+                let duration = parsed.get("duration").and_then(|v| v.as_u64()).unwrap_or(0);
+                if duration == 0 || duration > MAX_PAUSE_SECS {
+                    return json_response(
+                        400,
+                        &serde_json::json!({
+                            "error": format!("duration must be 1..{} seconds", MAX_PAUSE_SECS)
+                        })
+                        .to_string(),
+                    );
+                }
+                if let Err(e) = tunnel.pause(duration) {
+                    return json_response(400, &serde_json::json!({"error": e}).to_string());
+                }
+                let until = crate::util::unix_now() + duration;
+                self.note_manual(id);
+                self.tunnels.set_desired(id, DesiredState::Paused { until });
+                json_response(
+                    200,
+                    &serde_json::json!({"status": "paused", "resume_in": duration}).to_string(),
+                )
+            }
+            "resume" => match tunnel.resume() {
+                Ok(()) => {
+                    self.note_manual(id);
+                    self.tunnels.set_desired(id, DesiredState::Connected);
+                    json_response(200, r#"{"status":"connecting"}"#)
+                }
+                Err(e) => json_response(400, &serde_json::json!({"error": e}).to_string()),
+            },
+            "restart" => match tunnel.restart() {
+                Ok(()) => {
+                    self.note_manual(id);
+                    self.tunnels.set_desired(id, DesiredState::Connected);
+                    json_response(200, r#"{"status":"restarting"}"#)
+                }
                 Err(e) => json_response(400, &serde_json::json!({"error": e}).to_string()),
             },
             _ => json_response(400, r#"{"error":"unknown action"}"#),
@@ -265,6 +438,227 @@ impl WebUI {
         });
         json_response(200, &body.to_string())
     }
+
+    fn api_traffic(&self, id: &str, request: &Request) -> Response<std::io::Cursor<Vec<u8>>> {
+        let resolution = match parse_query_param(request.url(), "resolution") {
+            None => Resolution::Minute,
+            Some(value) => match Resolution::parse(&value) {
+                Some(r) => r,
+                None => return json_response(
+                    400,
+                    &serde_json::json!({
+                        "error": format!("unknown resolution '{}' (second, minute, hour)", value)
+                    })
+                    .to_string(),
+                ),
+            },
+        };
+        match self.tunnel_or_404(id) {
+            Ok(tunnel) => json_response(
+                200,
+                &serde_json::json!({
+                    "resolution": resolution.as_str(),
+                    "current": tunnel.traffic(),
+                    "buckets": tunnel.traffic_history(resolution),
+                })
+                .to_string(),
+            ),
+            Err(resp) => resp,
+        }
+    }
+
+    fn api_quality(&self, id: &str, request: &Request) -> Response<std::io::Cursor<Vec<u8>>> {
+        let minutes = parse_query_param(request.url(), "minutes")
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(24 * 60)
+            .clamp(1, 24 * 60);
+        match self.tunnel_or_404(id) {
+            Ok(tunnel) => {
+                let series = tunnel.quality_series(minutes);
+                json_response(
+                    200,
+                    &serde_json::json!({
+                        "enabled": series.is_some(),
+                        "minutes": minutes,
+                        "targets": series.unwrap_or_default(),
+                    })
+                    .to_string(),
+                )
+            }
+            Err(resp) => resp,
+        }
+    }
+
+    fn api_events(&self, id: &str, request: &Request) -> Response<std::io::Cursor<Vec<u8>>> {
+        let limit = parse_query_param(request.url(), "limit")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(100)
+            .min(500);
+        match self.tunnel_or_404(id) {
+            Ok(tunnel) => json_response(
+                200,
+                &serde_json::json!({ "events": tunnel.events(limit) }).to_string(),
+            ),
+            Err(resp) => resp,
+        }
+    }
+
+    fn api_quota(&self, id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+        match self.tunnel_or_404(id) {
+            Ok(tunnel) => {
+                let status = tunnel.quota_status();
+                json_response(
+                    200,
+                    &serde_json::json!({
+                        "enabled": status.is_some(),
+                        "quota": status,
+                    })
+                    .to_string(),
+                )
+            }
+            Err(resp) => resp,
+        }
+    }
+
+    fn api_quota_reset(&self, id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+        match self.tunnel_or_404(id) {
+            Ok(tunnel) if tunnel.reset_quota() => json_response(200, r#"{"status":"reset"}"#),
+            Ok(_) => json_response(400, r#"{"error":"quota is not enabled"}"#),
+            Err(resp) => resp,
+        }
+    }
+
+    fn api_sessions(&self, id: &str, request: &Request) -> Response<std::io::Cursor<Vec<u8>>> {
+        let limit = parse_query_param(request.url(), "limit")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(100)
+            .min(1000);
+        match self.tunnel_or_404(id) {
+            Ok(tunnel) => {
+                let sessions = tunnel.sessions(limit);
+                json_response(
+                    200,
+                    &serde_json::json!({
+                        "enabled": sessions.is_some(),
+                        "sessions": sessions.unwrap_or_default(),
+                    })
+                    .to_string(),
+                )
+            }
+            Err(resp) => resp,
+        }
+    }
+
+    fn api_route_lists(&self) -> Response<std::io::Cursor<Vec<u8>>> {
+        let sources: Vec<serde_json::Value> = self
+            .route_lists
+            .statuses()
+            .into_iter()
+            .map(|s| {
+                serde_json::json!({
+                    "name": s.name,
+                    "kind": s.kind,
+                    "location": s.location,
+                    "target": s.target,
+                    "tunnel": s.tunnel,
+                    "enabled": s.enabled,
+                    "prefixes": s.prefixes,
+                    "last_fetch": s.last_fetch,
+                    "last_status": s.last_status,
+                    "last_error": s.last_error,
+                })
+            })
+            .collect();
+        json_response(200, &serde_json::json!({ "sources": sources }).to_string())
+    }
+
+    fn api_route_lists_refresh(&self) -> Response<std::io::Cursor<Vec<u8>>> {
+        let lists = self.route_lists.clone();
+        let spawned = std::thread::Builder::new()
+            .name("route-lists-refresh".into())
+            .spawn(move || lists.refresh_all());
+        match spawned {
+            Ok(_) => json_response(200, r#"{"status":"refreshing"}"#),
+            Err(e) => json_response(
+                500,
+                &serde_json::json!({"error": format!("failed to start refresh: {}", e)})
+                    .to_string(),
+            ),
+        }
+    }
+
+    fn api_diagnostics_ndm(&self, id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+        let diag = match self.tunnel_or_404(id) {
+            Ok(tunnel) => tunnel.ndm_diagnostics(),
+            Err(resp) => return resp,
+        };
+        match serde_json::to_string(&diag) {
+            Ok(body) => json_response(200, &body),
+            Err(e) => json_response(
+                500,
+                &serde_json::json!({"error": e.to_string()}).to_string(),
+            ),
+        }
+    }
+
+    /// DNS leak check for the tunnel that owns the default route (`main`
+    /// when no tunnel does).
+    fn api_diagnostics_dns(&self) -> Response<std::io::Cursor<Vec<u8>>> {
+        let settings = self.config.lock().unwrap().dns.clone();
+        let tunnel = self
+            .tunnels
+            .iter()
+            .find(|(_, t)| t.owns_default_route())
+            .map(|(_, t)| t)
+            .or_else(|| self.tunnels.get(MAIN_TUNNEL_ID));
+        let Some(tunnel) = tunnel else {
+            return json_response(404, r#"{"error":"no tunnel configured"}"#);
+        };
+        match serde_json::to_string(&tunnel.dns_leak_check(&settings)) {
+            Ok(body) => json_response(200, &body),
+            Err(e) => json_response(
+                500,
+                &serde_json::json!({"error": e.to_string()}).to_string(),
+            ),
+        }
+    }
+
+    /// `/api/tunnels/{id}/{status|config|control|diagnostics/ndm}`.
+    fn handle_tunnel_request(
+        &self,
+        method: Method,
+        path: &str,
+        request: &mut Request,
+    ) -> Response<std::io::Cursor<Vec<u8>>> {
+        let rest = &path["/api/tunnels/".len()..];
+        let (id, action) = rest.split_once('/').unwrap_or((rest, ""));
+        match (method, action) {
+            (Method::Get, "status") => self.api_authed(request, |s| s.api_status(id)),
+            (Method::Get, "config") => self.api_authed(request, |s| s.api_get_config(id)),
+            (Method::Post, "config") => match read_body(request) {
+                Ok(body) => self.api_authed(request, |s| s.api_set_config(id, &body)),
+                Err(e) => json_response(413, &serde_json::json!({"error": e}).to_string()),
+            },
+            (Method::Post, "control") => match read_body(request) {
+                Ok(body) => self.api_authed(request, |s| s.api_control(id, &body)),
+                Err(e) => json_response(413, &serde_json::json!({"error": e}).to_string()),
+            },
+            (Method::Get, "diagnostics/ndm") => {
+                self.api_authed(request, |s| s.api_diagnostics_ndm(id))
+            }
+            (Method::Get, "events") => self.api_authed(request, |s| s.api_events(id, request)),
+            (Method::Get, "quota") => self.api_authed(request, |s| s.api_quota(id)),
+            (Method::Post, "quota/reset") => self.api_authed(request, |s| s.api_quota_reset(id)),
+            (Method::Get, "sessions") => self.api_authed(request, |s| s.api_sessions(id, request)),
+            (Method::Get, "metrics/quality") => {
+                self.api_authed(request, |s| s.api_quality(id, request))
+            }
+            (Method::Get, "metrics/traffic") => {
+                self.api_authed(request, |s| s.api_traffic(id, request))
+            }
+            _ => json_response(404, r#"{"error":"not found"}"#),
+        }
+    }
 }
 
 fn json_response(status: u16, body: &str) -> Response<std::io::Cursor<Vec<u8>>> {